@@ -0,0 +1,347 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use futures_util::{stream, StreamExt};
+use git2::{FetchOptions, FetchPrune};
+use crate::config::BackupOptions;
+use crate::export::{data_dir, ExportError};
+use crate::github::{GhRepo, GitHub, TargetKind};
+use crate::issues::export_issues;
+use crate::lfs::backup_lfs;
+use crate::metadata::export_metadata;
+use crate::pulls::export_pulls;
+use crate::releases::export_releases;
+use crate::mirror::{mirror_repo, repo_dir, MirrorError, Mirrored};
+use crate::report::Outcome;
+use crate::retry::backoff;
+use crate::state::BackupState;
+
+/// State shared by all backup jobs of a run.
+pub struct Context {
+    pub github: GitHub,
+    pub username: String,
+    pub token: String,
+    pub retries: u32,
+    /// Fetch repositories even if they did not change since the last backup.
+    pub full: bool,
+}
+
+/// A backup target whose name and backup directory have been resolved.
+pub struct Backup {
+    pub kind: TargetKind,
+    pub backup_dir: PathBuf,
+    pub options: BackupOptions,
+    /// State of the previous run, updated as repositories are backed up.
+    pub state: Mutex<BackupState>,
+}
+
+/// A repository to back up.
+pub struct Job {
+    pub backup: Arc<Backup>,
+    pub repo: GhRepo,
+}
+
+/// A git repository which is mirrored together with data exported from the API.
+pub trait BackupJob: Send + Sync + 'static {
+    /// Returns the name of the job in messages and the summary.
+    fn name(&self) -> String;
+
+    /// Returns the URL of the mirrored repository.
+    fn url(&self) -> &str;
+
+    /// Mirrors the repository. This blocks, so it runs on a separate thread.
+    fn mirror(&self, ctx: &Context) -> Result<Mirrored, BackupError>;
+
+    /// Exports the data which is not stored in git once the repository was mirrored.
+    fn export(&self, ctx: &Context) -> impl Future<Output = Result<(), BackupError>> + Send;
+
+    /// Records the result of the job once it is done.
+    fn finish(&self, _result: &Result<Mirrored, BackupError>) {}
+}
+
+impl BackupJob for Job {
+    fn name(&self) -> String {
+        self.repo.full_name.clone()
+    }
+
+    fn url(&self) -> &str {
+        &self.repo.clone_url
+    }
+
+    fn mirror(&self, ctx: &Context) -> Result<Mirrored, BackupError> {
+        backup_repo(ctx, self)
+    }
+
+    async fn export(&self, ctx: &Context) -> Result<(), BackupError> {
+        export_repo(ctx, self).await
+    }
+
+    fn finish(&self, result: &Result<Mirrored, BackupError>) {
+        if let Ok(mut state) = self.backup.state.lock() {
+            match result {
+                Ok(_) => state.backed_up(&self.repo),
+                Err(_) => state.failed(&self.repo),
+            }
+        }
+    }
+}
+
+pub enum BackupErrorKind {
+    Mirror(MirrorError),
+    Wiki(MirrorError),
+    Issues(ExportError),
+    Pulls(ExportError),
+    Releases(ExportError),
+    Lfs(ExportError),
+    Metadata(ExportError),
+    GistComments(ExportError),
+    /// The backup task panicked or was cancelled.
+    Task(String),
+}
+
+impl Debug for BackupErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupErrorKind::Mirror(e) => write!(f, "{}", e),
+            BackupErrorKind::Wiki(e) => write!(f, "Wiki: {}", e),
+            BackupErrorKind::Issues(e) => write!(f, "Issues: {}", e),
+            BackupErrorKind::Pulls(e) => write!(f, "Pull requests: {}", e),
+            BackupErrorKind::Releases(e) => write!(f, "Releases: {}", e),
+            BackupErrorKind::Lfs(e) => write!(f, "LFS: {}", e),
+            BackupErrorKind::Metadata(e) => write!(f, "Metadata: {}", e),
+            BackupErrorKind::GistComments(e) => write!(f, "Gist comments: {}", e),
+            BackupErrorKind::Task(e) => write!(f, "Backup task failed: {}", e),
+        }
+    }
+}
+
+impl Display for BackupErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Failure to back up a single repository.
+pub struct BackupError {
+    pub repo: String,
+    pub kind: BackupErrorKind,
+}
+
+impl BackupError {
+    pub fn is_transient(&self) -> bool {
+        match &self.kind {
+            BackupErrorKind::Mirror(e) | BackupErrorKind::Wiki(e) => e.is_transient(),
+            BackupErrorKind::Issues(_)
+            | BackupErrorKind::Pulls(_)
+            | BackupErrorKind::Releases(_)
+            | BackupErrorKind::Lfs(_)
+            | BackupErrorKind::Metadata(_)
+            | BackupErrorKind::GistComments(_)
+            | BackupErrorKind::Task(_) => false,
+        }
+    }
+}
+
+impl Debug for BackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.repo, self.kind)
+    }
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for BackupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            BackupErrorKind::Mirror(e) | BackupErrorKind::Wiki(e) => Some(e),
+            BackupErrorKind::Issues(e)
+            | BackupErrorKind::Pulls(e)
+            | BackupErrorKind::Releases(e)
+            | BackupErrorKind::Lfs(e)
+            | BackupErrorKind::Metadata(e)
+            | BackupErrorKind::GistComments(e) => Some(e),
+            BackupErrorKind::Task(_) => None,
+        }
+    }
+}
+
+/// Mirrors `url` into `repo_dir`, retrying transient errors.
+pub fn mirror_with_retries(ctx: &Context, name: &str, repo_dir: &Path, url: &str, bare: bool) -> Result<Mirrored, MirrorError> {
+    let mut attempt = 0;
+    loop {
+        let mut cb = git2::RemoteCallbacks::new();
+        cb.credentials(|_, _, _| git2::Cred::userpass_plaintext(&ctx.username, &ctx.token));
+
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(cb)
+            .download_tags(git2::AutotagOption::All)
+            .prune(FetchPrune::On)
+            .update_fetchhead(true);
+
+        match mirror_repo(repo_dir, url, bare, &mut fo) {
+            Err(e) if e.is_transient() && attempt < ctx.retries => {
+                attempt += 1;
+                let delay = backoff(attempt);
+                eprintln!("Retrying {} in {:.1}s after: {}", name, delay.as_secs_f32(), e);
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Mirrors the wiki of the job's repository next to it.
+///
+/// GitHub reports `has_wiki` even if no page was ever created. In that case the wiki repository does not exist,
+/// which is not an error.
+fn backup_wiki(ctx: &Context, job: &Job) -> Result<(), MirrorError> {
+    let bare = job.backup.options.bare;
+    let wiki_path = format!("{}.wiki", job.backup.kind.repo_path(&job.repo));
+    let wiki_dir = repo_dir(&job.backup.backup_dir, &wiki_path, bare);
+
+    let name = format!("{} wiki", job.repo.full_name);
+    match mirror_with_retries(ctx, &name, &wiki_dir, &job.repo.wiki_clone_url(), bare) {
+        Err(e) if e.is_not_found() => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+pub fn backup_repo(ctx: &Context, job: &Job) -> Result<Mirrored, BackupError> {
+    let error = |kind| BackupError {
+        repo: job.repo.full_name.clone(),
+        kind,
+    };
+
+    let bare = job.backup.options.bare;
+    let repo_dir = repo_dir(&job.backup.backup_dir, job.backup.kind.repo_path(&job.repo), bare);
+    let unchanged = !ctx.full
+        && repo_dir.exists()
+        && job.backup.state.lock().is_ok_and(|state| state.is_unchanged(&job.repo));
+
+    let mirrored = if unchanged {
+        Mirrored::Unchanged
+    } else {
+        mirror_with_retries(ctx, &job.repo.full_name, &repo_dir, &job.repo.clone_url, bare)
+            .map_err(|e| error(BackupErrorKind::Mirror(e)))?
+    };
+
+    // Pushes to the wiki do not move the timestamps of the repository, so it is fetched even if the repository
+    // is unchanged.
+    if job.backup.options.wiki && job.repo.has_wiki {
+        backup_wiki(ctx, job).map_err(|e| error(BackupErrorKind::Wiki(e)))?;
+    }
+
+    Ok(mirrored)
+}
+
+/// Exports the data of the job's repository which is not stored in git from the API.
+///
+/// LFS objects are looked for even if the mirror is unchanged, as `--lfs` may have been turned on after its last
+/// backup. Objects which are present already are not downloaded again.
+async fn export_repo(ctx: &Context, job: &Job) -> Result<(), BackupError> {
+    let error = |kind| BackupError {
+        repo: job.repo.full_name.clone(),
+        kind,
+    };
+
+    let path = job.backup.kind.repo_path(&job.repo);
+    let data_dir = data_dir(&job.backup.backup_dir, path);
+
+    if job.backup.options.metadata {
+        export_metadata(&ctx.github, &job.repo.full_name, &data_dir).await
+            .map_err(|e| error(BackupErrorKind::Metadata(e)))?;
+    }
+
+    if job.backup.options.lfs {
+        let repo_dir = repo_dir(&job.backup.backup_dir, path, job.backup.options.bare);
+        let downloaded = backup_lfs(ctx.github.client(), &repo_dir, &job.repo.clone_url, &ctx.username, &ctx.token).await
+            .map_err(|e| error(BackupErrorKind::Lfs(e)))?;
+        if downloaded > 0 {
+            println!("Downloaded {} LFS objects of {}", downloaded, job.repo.full_name);
+        }
+    }
+
+    if job.backup.options.issues && job.repo.has_issues {
+        export_issues(&ctx.github, &job.repo.full_name, &data_dir.join("issues")).await
+            .map_err(|e| error(BackupErrorKind::Issues(e)))?;
+    }
+
+    if job.backup.options.pulls {
+        export_pulls(&ctx.github, &job.repo.full_name, &data_dir.join("pulls")).await
+            .map_err(|e| error(BackupErrorKind::Pulls(e)))?;
+    }
+
+    if job.backup.options.releases {
+        export_releases(&ctx.github, &job.repo.full_name, &data_dir.join("releases")).await
+            .map_err(|e| error(BackupErrorKind::Releases(e)))?;
+    }
+
+    Ok(())
+}
+
+async fn run_jobs<J: BackupJob>(ctx: &Arc<Context>, jobs: Vec<Arc<J>>, parallel: usize) -> Vec<(Arc<J>, Outcome, Duration)> {
+    // The stream is lazy, so at most `parallel` blocking libgit2 tasks are spawned at the same time.
+    stream::iter(jobs)
+        .map(|job| {
+            let started = Instant::now();
+            let task_ctx = ctx.clone();
+            let task_job = job.clone();
+            let handle = tokio::task::spawn_blocking(move || {
+                println!("Started to backup: {} from {}", task_job.name(), task_job.url());
+                task_job.mirror(&task_ctx)
+            });
+
+            async move {
+                let result = handle.await.unwrap_or_else(|e| Err(BackupError {
+                    repo: job.name(),
+                    kind: BackupErrorKind::Task(e.to_string()),
+                }));
+
+                let result = match result {
+                    Ok(mirrored) => job.export(ctx).await.map(|_| mirrored),
+                    Err(e) => Err(e),
+                };
+                job.finish(&result);
+
+                match result {
+                    Ok(mirrored) => (job, Outcome::from(mirrored), started.elapsed()),
+                    Err(e) => {
+                        eprintln!("Failed to backup {}", e);
+                        (job, Outcome::Failed(e), started.elapsed())
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .collect().await
+}
+
+/// Backs up all `jobs`, at most `parallel` at the same time. Returns the outcome of each job and how long it took.
+///
+/// Jobs which failed due to network or server problems get a final chance after all others are done, as these are
+/// often short outages.
+pub async fn backup_all<J: BackupJob>(ctx: &Arc<Context>, jobs: Vec<Arc<J>>, parallel: usize) -> Vec<(Arc<J>, Outcome, Duration)> {
+    let results = run_jobs(ctx, jobs, parallel).await;
+    if ctx.retries == 0 {
+        return results;
+    }
+
+    let (retry, mut done): (Vec<_>, Vec<_>) = results.into_iter().partition(|(_, outcome, _)| {
+        matches!(outcome, Outcome::Failed(e) if e.is_transient())
+    });
+
+    if !retry.is_empty() {
+        println!("Retrying {} failed backups", retry.len());
+        let retry = retry.into_iter().map(|(job, _, _)| job).collect();
+        done.extend(run_jobs(ctx, retry, parallel).await);
+    }
+    done
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_derive::Deserialize;
+use crate::filter::ArchivedFilter;
+use crate::github::TargetKind;
+
+/// Options which can be set per backup target.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BackupOptions {
+    pub bare: bool,
+    pub wiki: bool,
+    pub issues: bool,
+    pub pulls: bool,
+    pub releases: bool,
+    pub lfs: bool,
+    pub gists: bool,
+    pub metadata: bool,
+    pub organisation: bool,
+    /// Glob patterns of the repositories to back up. All repositories are backed up if empty.
+    pub include: Vec<String>,
+    /// Glob patterns of the repositories to leave out.
+    pub exclude: Vec<String>,
+    pub no_forks: bool,
+    pub archived: ArchivedFilter,
+    /// Visibilities of the repositories to back up. All are backed up if empty.
+    pub visibility: Vec<String>,
+    /// Size in MiB above which repositories are left out.
+    pub max_size: Option<u64>,
+    /// Repositories with any of these topics are backed up. All are backed up if empty.
+    #[serde(rename = "topic")]
+    pub topics: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    #[serde(default)]
+    pub kind: TargetKind,
+    pub name: Option<String>,
+    pub backup_dir: Option<PathBuf>,
+    #[serde(flatten)]
+    pub options: BackupOptions,
+    /// Keys which are not known. Serde cannot deny them for flattened structs, so they are collected and rejected
+    /// when loading the config.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Target {
+    /// Checks that a name is given exactly when the target kind requires one, and that the options suit the kind.
+    pub fn check(&self) -> Result<(), &'static str> {
+        match (self.kind, &self.name) {
+            (TargetKind::Authenticated, Some(_)) => Err("The target name must be omitted for kind me."),
+            (TargetKind::Organisation | TargetKind::User, None) => Err("Name of the organisation or user is missing."),
+            (TargetKind::User | TargetKind::Authenticated, _) if self.options.organisation => {
+                Err("The organisation structure can only be exported for kind org.")
+            }
+            _ if self.options.visibility.iter().any(|v| !["public", "private", "internal"].contains(&v.as_str())) => {
+                Err("The visibility must be public, private or internal.")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub api_url: Option<String>,
+    pub jobs: Option<usize>,
+    pub retries: Option<u32>,
+    #[serde(rename = "target")]
+    pub targets: Vec<Target>,
+}
+
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    NoTargets,
+    UnknownKey(String),
+}
+
+impl Debug for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Failed to read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "Failed to parse config: {}", e),
+            ConfigError::NoTargets => write!(f, "Config does not contain any targets."),
+            ConfigError::UnknownKey(key) => write!(f, "Unknown key `{}` in target of config.", key),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let content = fs::read_to_string(path).map_err(ConfigError::Io)?;
+    let config: Config = toml::from_str(&content).map_err(ConfigError::Parse)?;
+
+    if config.targets.is_empty() {
+        return Err(ConfigError::NoTargets);
+    }
+
+    if let Some(key) = config.targets.iter().flat_map(|target| target.unknown.keys()).next() {
+        return Err(ConfigError::UnknownKey(key.clone()));
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use reqwest::Response;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use crate::github::ApiError;
+
+/// Directory in the backup directory for everything which is not the backup of a repository of the target. It is
+/// the only name a repository cannot have, so nothing else in the backup directory can collide with a repository.
+pub const RESERVED_DIR: &str = ".gh-backup";
+
+pub fn reserved_dir(backup_dir: &Path) -> PathBuf {
+    backup_dir.join(RESERVED_DIR)
+}
+
+/// Returns the directory next to the mirror at the relative `path` which holds data that is not stored in git,
+/// like issues.
+pub fn data_dir(backup_dir: &Path, path: &str) -> PathBuf {
+    backup_dir.join(format!("{}.github", path))
+}
+
+/// Returns `path` with `suffix` appended to its file name. Unlike [`Path::with_extension`], this never yields the
+/// name of a sibling, e.g. `foo.download` for `foo.zip`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub enum ExportError {
+    Api(ApiError),
+    Io(io::Error),
+    /// A downloaded file does not match the size or checksum reported by the API.
+    Verification(String),
+    Git(git2::Error),
+    /// The Git LFS server rejected a request.
+    Lfs(String),
+}
+
+impl Debug for ExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Api(e) => write!(f, "{}", e),
+            ExportError::Io(e) => write!(f, "Failed to write export: {}", e),
+            ExportError::Verification(name) => write!(f, "Download of {} is corrupted.", name),
+            ExportError::Git(e) => write!(f, "Failed to read repository: {}", e.message()),
+            ExportError::Lfs(e) => write!(f, "LFS server error: {}", e),
+        }
+    }
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ExportError {}
+
+impl From<ApiError> for ExportError {
+    fn from(e: ApiError) -> Self {
+        ExportError::Api(e)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+/// Streams the body of `response` to `path` and returns its SHA-256 checksum. The body is verified against the
+/// expected `size` and, if given, the expected `sha256` before it is moved into place, so `path` never holds a
+/// corrupted download. `name` identifies the download in errors.
+pub async fn download_verified(
+    mut response: Response,
+    path: &Path,
+    size: u64,
+    sha256: Option<&str>,
+    name: &str,
+) -> Result<String, ExportError> {
+    let tmp = with_suffix(path, ".download");
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    let mut hasher = Sha256::new();
+    let mut received = 0;
+    while let Some(chunk) = response.chunk().await.map_err(|_| ExportError::Verification(name.to_string()))? {
+        hasher.update(&chunk);
+        received += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    let checksum = format!("{:x}", hasher.finalize());
+    if received != size || sha256.is_some_and(|sha256| sha256 != checksum) {
+        let _ = fs::remove_file(&tmp);
+        return Err(ExportError::Verification(name.to_string()));
+    }
+
+    fs::rename(tmp, path)?;
+    Ok(checksum)
+}
+
+/// Writes `value` as pretty printed JSON. The file is replaced atomically, so an interrupted run never leaves a
+/// truncated file behind.
+pub fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_vec_pretty(value)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)
+}
+
+/// Reads JSON written by [`write_json`]. Returns `None` if the file does not exist or cannot be parsed.
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let json = fs::read(path).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Returns the fields which differ between `old` and `new` with their old and new value.
+pub fn changed_fields(old: &Map<String, Value>, new: &Map<String, Value>) -> Map<String, Value> {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| (key.clone(), json!({
+            "old": old.get(key).unwrap_or(&Value::Null),
+            "new": new.get(key).unwrap_or(&Value::Null),
+        })))
+        .collect()
+}
+
+/// Formats `time` as RFC 3339 timestamp in seconds.
+pub fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Formats `time` like [`timestamp`], but usable in file names. Colons are not allowed in file names on all
+/// platforms.
+pub fn file_timestamp(time: SystemTime) -> String {
+    timestamp(time).replace(':', "-")
+}
+
+/// Appends `changes` with the current time to the JSON list at `path`, unless there are none.
+pub fn record_changes(path: &Path, changes: Map<String, Value>) -> io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut history: Vec<Value> = read_json(path).unwrap_or_default();
+    history.push(json!({
+        "recorded_at": timestamp(SystemTime::now()),
+        "changes": changes,
+    }));
+    write_json(path, &history)
+}
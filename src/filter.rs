@@ -0,0 +1,128 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use glob::Pattern;
+use serde_derive::Deserialize;
+use crate::config::BackupOptions;
+use crate::github::GhRepo;
+
+/// Name of the file in the backup directory which lists patterns of repositories to exclude, one per line.
+pub const IGNORE_FILE: &str = ".gh-backup-ignore";
+
+/// Which repositories to back up depending on whether they are archived.
+#[derive(Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivedFilter {
+    #[default]
+    Include,
+    Skip,
+    Only,
+}
+
+impl FromStr for ArchivedFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "include" => Ok(ArchivedFilter::Include),
+            "skip" => Ok(ArchivedFilter::Skip),
+            "only" => Ok(ArchivedFilter::Only),
+            _ => Err(format!("unknown archived filter `{}`, expected skip, only or include", s)),
+        }
+    }
+}
+
+pub enum FilterError {
+    Pattern(String, glob::PatternError),
+    IgnoreFile(io::Error),
+}
+
+impl Debug for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterError::Pattern(pattern, e) => write!(f, "Invalid pattern {}: {}", pattern, e),
+            FilterError::IgnoreFile(e) => write!(f, "Failed to read {}: {}", IGNORE_FILE, e),
+        }
+    }
+}
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+fn compile(patterns: &[String]) -> Result<Vec<Pattern>, FilterError> {
+    patterns.iter()
+        .map(|pattern| Pattern::new(pattern).map_err(|e| FilterError::Pattern(pattern.clone(), e)))
+        .collect()
+}
+
+/// Reads the patterns of the ignore file in `backup_dir`. Empty lines and lines starting with `#` are skipped.
+fn read_ignore_file(backup_dir: &Path) -> Result<Vec<String>, FilterError> {
+    match fs::read_to_string(backup_dir.join(IGNORE_FILE)) {
+        Ok(content) => Ok(content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(FilterError::IgnoreFile(e)),
+    }
+}
+
+/// Selects the repositories of a target by glob patterns matched against their `name` and `full_name` and by
+/// properties of the listing.
+pub struct RepoFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    no_forks: bool,
+    archived: ArchivedFilter,
+    visibility: Vec<String>,
+    /// Maximum size in KiB, the unit of the API.
+    max_size: Option<u64>,
+    topics: Vec<String>,
+}
+
+impl RepoFilter {
+    /// Creates the filter from the patterns of `options` and the ignore file in `backup_dir`.
+    pub fn new(options: &BackupOptions, backup_dir: &Path) -> Result<Self, FilterError> {
+        let mut exclude = compile(&options.exclude)?;
+        exclude.extend(compile(&read_ignore_file(backup_dir)?)?);
+
+        Ok(RepoFilter {
+            include: compile(&options.include)?,
+            exclude,
+            no_forks: options.no_forks,
+            archived: options.archived,
+            visibility: options.visibility.clone(),
+            max_size: options.max_size.map(|mib| mib.saturating_mul(1024)),
+            topics: options.topics.clone(),
+        })
+    }
+
+    /// Returns whether `repo` matches any include pattern, if there are any, and no exclude pattern, and has the
+    /// required properties.
+    pub fn matches(&self, repo: &GhRepo) -> bool {
+        let matches = |pattern: &Pattern| pattern.matches(&repo.name) || pattern.matches(&repo.full_name);
+        if !(self.include.is_empty() || self.include.iter().any(matches)) || self.exclude.iter().any(matches) {
+            return false;
+        }
+
+        let archived = match self.archived {
+            ArchivedFilter::Include => true,
+            ArchivedFilter::Skip => !repo.archived,
+            ArchivedFilter::Only => repo.archived,
+        };
+
+        archived
+            && !(self.no_forks && repo.fork)
+            && (self.visibility.is_empty() || self.visibility.iter().any(|visibility| visibility == repo.visibility()))
+            && self.max_size.is_none_or(|max_size| repo.size <= max_size)
+            && (self.topics.is_empty() || self.topics.iter().any(|topic| repo.topics.contains(topic)))
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde_json::Value;
+use crate::backup::{mirror_with_retries, Backup, BackupError, BackupErrorKind, BackupJob, Context};
+use crate::export::{data_dir, write_json, ExportError, RESERVED_DIR};
+use crate::github::{ApiError, GhGist, GitHub, TargetKind};
+use crate::mirror::{repo_dir, Mirrored};
+
+/// A gist to back up.
+pub struct GistJob {
+    pub backup: Arc<Backup>,
+    pub owner: String,
+    pub gist: GhGist,
+}
+
+impl GistJob {
+    /// Returns the path of the gist relative to the backup directory. Gists are kept in the reserved directory, so
+    /// they cannot collide with a repository named `gists`.
+    pub fn path(&self) -> String {
+        format!("{}/gists/{}/{}", RESERVED_DIR, self.owner, self.gist.id)
+    }
+}
+
+/// Fetches the gists of a backup target together with the login of their owner. These are the gists of all members
+/// for organisations, since gists belong to user accounts.
+pub async fn fetch_target_gists(github: &GitHub, kind: TargetKind, name: &str) -> Result<Vec<(String, GhGist)>, ApiError> {
+    let mut gists = vec![];
+    match kind {
+        TargetKind::Organisation => {
+            for member in github.fetch_members(name).await? {
+                let member_gists = github.fetch_gists(Some(&member.login)).await?;
+                gists.extend(member_gists.into_iter().map(|gist| (member.login.clone(), gist)));
+            }
+        }
+        TargetKind::User => {
+            gists.extend(github.fetch_gists(Some(name)).await?.into_iter().map(|gist| (name.to_string(), gist)));
+        }
+        TargetKind::Authenticated => {
+            gists.extend(github.fetch_gists(None).await?.into_iter().map(|gist| (name.to_string(), gist)));
+        }
+    }
+
+    Ok(gists)
+}
+
+/// Exports the comments of the gist to `comments.json`, which is only written if there are any.
+async fn export_comments(github: &GitHub, job: &GistJob, dir: PathBuf) -> Result<(), ExportError> {
+    if job.gist.comments == 0 {
+        return Ok(());
+    }
+
+    let comments: Vec<Value> = github.get_all(github.url(&format!("/gists/{}/comments", job.gist.id))?).await?;
+    write_json(&dir.join("comments.json"), &comments)?;
+    Ok(())
+}
+
+impl BackupJob for GistJob {
+    fn name(&self) -> String {
+        format!("gist {}/{}", self.owner, self.gist.id)
+    }
+
+    fn url(&self) -> &str {
+        &self.gist.git_pull_url
+    }
+
+    fn mirror(&self, ctx: &Context) -> Result<Mirrored, BackupError> {
+        let bare = self.backup.options.bare;
+        let repo_dir = repo_dir(&self.backup.backup_dir, &self.path(), bare);
+        mirror_with_retries(ctx, &self.name(), &repo_dir, &self.gist.git_pull_url, bare)
+            .map_err(|e| BackupError {
+                repo: self.name(),
+                kind: BackupErrorKind::Mirror(e),
+            })
+    }
+
+    async fn export(&self, ctx: &Context) -> Result<(), BackupError> {
+        let dir = data_dir(&self.backup.backup_dir, &self.path());
+        export_comments(&ctx.github, self, dir).await
+            .map_err(|e| BackupError {
+                repo: self.name(),
+                kind: BackupErrorKind::GistComments(e),
+            })
+    }
+}
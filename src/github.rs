@@ -0,0 +1,434 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::header::{HeaderMap, LINK, RETRY_AFTER};
+use reqwest::{Client, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use crate::retry::backoff;
+
+#[derive(Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum TargetKind {
+    /// Repositories of an organisation.
+    #[default]
+    #[serde(rename = "org")]
+    Organisation,
+    /// Public repositories of a user.
+    #[serde(rename = "user")]
+    User,
+    /// All repositories the authenticated user has access to, including private ones and those of organisations
+    /// the user is a member of.
+    #[serde(rename = "me")]
+    Authenticated,
+}
+
+impl FromStr for TargetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "org" => Ok(TargetKind::Organisation),
+            "user" => Ok(TargetKind::User),
+            "me" => Ok(TargetKind::Authenticated),
+            _ => Err(format!("unknown target kind `{}`, expected org, user or me", s)),
+        }
+    }
+}
+
+impl TargetKind {
+    /// Returns the API path and query parameters for listing the repositories of the target `name`.
+    pub fn repos_endpoint(&self, name: &str) -> (String, &'static [(&'static str, &'static str)]) {
+        match self {
+            TargetKind::Organisation => (format!("/orgs/{}/repos", name), &[("type", "all")]),
+            TargetKind::User => (format!("/users/{}/repos", name), &[("type", "all")]),
+            TargetKind::Authenticated => (
+                "/user/repos".to_string(),
+                &[("affiliation", "owner,collaborator,organization_member"), ("visibility", "all")],
+            ),
+        }
+    }
+
+    /// Returns the path of `repo` relative to the backup directory.
+    ///
+    /// The authenticated user can access repositories of several owners, which may share names. Their backups
+    /// are therefore grouped by owner.
+    pub fn repo_path<'a>(&self, repo: &'a GhRepo) -> &'a str {
+        match self {
+            TargetKind::Authenticated => &repo.full_name,
+            _ => &repo.name,
+        }
+    }
+
+    /// Like [`TargetKind::repo_path`], but for a repository of which only the full name is known.
+    pub fn full_name_path<'a>(&self, full_name: &'a str) -> &'a str {
+        match (self, full_name.split_once('/')) {
+            (TargetKind::Authenticated, _) | (_, None) => full_name,
+            (_, Some((_, name))) => name,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GhRepo {
+    pub id: u64,
+    pub name: String,
+    pub full_name: String,
+    pub clone_url: String,
+    pub size: u64,
+    #[serde(default)]
+    pub has_wiki: bool,
+    #[serde(default)]
+    pub has_issues: bool,
+    pub pushed_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub fork: bool,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub private: bool,
+    /// Only reported by servers which support internal repositories.
+    pub visibility: Option<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+impl GhRepo {
+    /// Returns the clone URL of the wiki, which lives in a separate repository.
+    pub fn wiki_clone_url(&self) -> String {
+        let url = self.clone_url.strip_suffix(".git").unwrap_or(&self.clone_url);
+        format!("{}.wiki.git", url)
+    }
+
+    /// Returns whether the repository is public, private or internal.
+    pub fn visibility(&self) -> &str {
+        match &self.visibility {
+            Some(visibility) => visibility,
+            None if self.private => "private",
+            None => "public",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GhUser {
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+pub struct GhGist {
+    pub id: String,
+    pub git_pull_url: String,
+    #[serde(default)]
+    pub comments: u64,
+}
+
+pub enum ApiError {
+    NotFound,
+    Forbidden,
+    RateLimited,
+    ServerError,
+    TooManyPages,
+    UnknownError,
+}
+
+impl Debug for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound => write!(f, "Not found."),
+            ApiError::Forbidden => write!(f, "Access forbidden."),
+            ApiError::RateLimited => write!(f, "Rate limit exceeded {} times in a row.", MAX_RATE_LIMIT_WAITS),
+            ApiError::ServerError => write!(f, "Server error."),
+            ApiError::TooManyPages => write!(f, "More than {} pages, refusing to truncate the listing.", MAX_PAGE),
+            ApiError::UnknownError => write!(f, "Unknown error.")
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ApiError {}
+
+pub enum FetchReposError {
+    OrganisationNotFound,
+    UserNotFound,
+    Api(ApiError),
+}
+
+impl Debug for FetchReposError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchReposError::OrganisationNotFound => write!(f, "Organisation not found."),
+            FetchReposError::UserNotFound => write!(f, "User not found."),
+            FetchReposError::Api(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Display for FetchReposError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// API URL of github.com. GitHub Enterprise Server serves the API at `https://<host>/api/v3`.
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+const MAX_PAGE: usize = 1000;
+const PER_PAGE: usize = 100;
+
+/// Number of consecutive rate limit waits after which a request is given up.
+const MAX_RATE_LIMIT_WAITS: usize = 10;
+
+/// Wait for secondary rate limits which do not state when to retry, as recommended by GitHub.
+const SECONDARY_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// Returns the URL marked with `rel="next"` in an RFC 5988 `Link` header.
+fn next_link(headers: &HeaderMap) -> Option<Url> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+
+    link.split(',').find_map(|entry| {
+        let mut parts = entry.split(';');
+        let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        if parts.any(|param| param.trim() == "rel=\"next\"") {
+            Url::parse(url).ok()
+        } else {
+            None
+        }
+    })
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Returns how long to wait if the response reports an exceeded primary or secondary rate limit.
+///
+/// Secondary rate limits may come without any headers. For a 403 this can only be told apart from missing
+/// permissions by the message in the body, which the caller has to check.
+fn rate_limit_wait(response: &Response) -> Option<Duration> {
+    let code = response.status();
+    if code != StatusCode::FORBIDDEN && code != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let headers = response.headers();
+    if let Some(seconds) = header_u64(headers, RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    if header_u64(headers, "x-ratelimit-remaining") == Some(0) {
+        let reset = header_u64(headers, "x-ratelimit-reset")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        return Some(Duration::from_secs(reset.saturating_sub(now) + 1));
+    }
+
+    if code == StatusCode::TOO_MANY_REQUESTS {
+        return Some(SECONDARY_RATE_LIMIT_WAIT);
+    }
+
+    None
+}
+
+/// Client for the GitHub REST API which waits for rate limits to reset.
+pub struct GitHub {
+    client: Client,
+    api_url: String,
+    token: String,
+    retries: u32,
+    rate_limit_waits: AtomicUsize,
+}
+
+impl GitHub {
+    pub fn new(client: Client, api_url: &str, token: &str, retries: u32) -> Self {
+        GitHub {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            retries,
+            rate_limit_waits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the HTTP client, for requests to servers other than the API.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns how often requests had to wait for a rate limit to reset.
+    pub fn rate_limit_waits(&self) -> usize {
+        self.rate_limit_waits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the URL of the API `path`, e.g. `/user`.
+    pub fn url(&self, path: &str) -> Result<Url, ApiError> {
+        Url::parse(&format!("{}{}", self.api_url, path)).map_err(|_| ApiError::UnknownError)
+    }
+
+    async fn wait_for_rate_limit(&self, url: &Url, wait: Duration) {
+        self.rate_limit_waits.fetch_add(1, Ordering::Relaxed);
+        eprintln!("Rate limit exceeded for {}, waiting {}s", url.path(), wait.as_secs());
+        tokio::time::sleep(wait).await;
+    }
+
+    /// Sends a GET request to `url` and returns the successful response.
+    ///
+    /// Failed connections and server errors are retried with backoff, rate limits are waited for.
+    async fn send(&self, url: &Url) -> Result<Response, ApiError> {
+        self.send_accepting(url, "application/vnd.github+json").await
+    }
+
+    /// Like [`GitHub::send`], but requests the media type `accept`.
+    async fn send_accepting(&self, url: &Url, accept: &str) -> Result<Response, ApiError> {
+        let mut attempt = 0;
+        let mut rate_limit_waits = 0;
+        loop {
+            let response = self.client.get(url.as_str())
+                .header("Accept", accept)
+                .header("User-Agent",  "request")
+                .bearer_auth(&self.token)
+                .send().await;
+
+            let transient_error = match response {
+                Err(_) => ApiError::UnknownError,
+                Ok(response) if response.status().is_server_error() => ApiError::ServerError,
+                Ok(response) => {
+                    if let Some(wait) = rate_limit_wait(&response) {
+                        if rate_limit_waits == MAX_RATE_LIMIT_WAITS {
+                            return Err(ApiError::RateLimited);
+                        }
+                        rate_limit_waits += 1;
+                        self.wait_for_rate_limit(url, wait).await;
+                        continue;
+                    }
+
+                    let code = response.status();
+                    if code == StatusCode::NOT_FOUND {
+                        return Err(ApiError::NotFound);
+                    }
+
+                    if code == StatusCode::FORBIDDEN {
+                        let message = response.text().await.unwrap_or_default();
+                        if message.contains("rate limit") {
+                            if rate_limit_waits == MAX_RATE_LIMIT_WAITS {
+                                return Err(ApiError::RateLimited);
+                            }
+                            rate_limit_waits += 1;
+                            self.wait_for_rate_limit(url, SECONDARY_RATE_LIMIT_WAIT).await;
+                            continue;
+                        }
+
+                        return Err(ApiError::Forbidden);
+                    }
+
+                    if !code.is_success() {
+                        return Err(ApiError::UnknownError);
+                    }
+
+                    return Ok(response);
+                }
+            };
+
+            if attempt == self.retries {
+                return Err(transient_error);
+            }
+            attempt += 1;
+
+            let delay = backoff(attempt);
+            eprintln!("Retrying {} in {:.1}s after: {}", url.path(), delay.as_secs_f32(), transient_error);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Requests the binary content at `url`, e.g. of a release asset. The body is left to the caller to stream.
+    pub async fn download(&self, url: &Url) -> Result<Response, ApiError> {
+        self.send_accepting(url, "application/octet-stream").await
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, url: &Url) -> Result<T, ApiError> {
+        self.send(url).await?
+            .json().await
+            .map_err(|_| ApiError::UnknownError)
+    }
+
+    /// Fetches all pages of a listing by following the `Link` headers, starting at `url`.
+    pub async fn get_all<T: DeserializeOwned>(&self, url: Url) -> Result<Vec<T>, ApiError> {
+        self.get_while(url, |_| true).await
+    }
+
+    /// Fetches pages of a listing like [`GitHub::get_all`], but stops at the first item for which `take` returns
+    /// false. This avoids fetching old items of listings sorted by date.
+    pub async fn get_while<T, F>(&self, url: Url, take: F) -> Result<Vec<T>, ApiError>
+    where
+        T: DeserializeOwned,
+        F: Fn(&T) -> bool,
+    {
+        let mut items = vec![];
+
+        let mut url = url;
+        url.query_pairs_mut().append_pair("per_page", &PER_PAGE.to_string());
+
+        let mut next = Some(url);
+        let mut pages = 0;
+        while let Some(url) = next {
+            if pages == MAX_PAGE {
+                return Err(ApiError::TooManyPages);
+            }
+            pages += 1;
+
+            let response = self.send(&url).await?;
+            next = next_link(response.headers());
+
+            let page: Vec<T> = response
+                .json().await
+                .map_err(|_| ApiError::UnknownError)?;
+
+            for item in page {
+                if !take(&item) {
+                    return Ok(items);
+                }
+                items.push(item);
+            }
+        }
+
+        Ok(items)
+    }
+
+    pub async fn fetch_user(&self) -> Result<GhUser, ApiError> {
+        self.get(&self.url("/user")?).await
+    }
+
+    pub async fn fetch_repos(&self, kind: TargetKind, name: &str) -> Result<Vec<GhRepo>, FetchReposError> {
+        let (path, params) = kind.repos_endpoint(name);
+
+        let mut url = self.url(&path).map_err(FetchReposError::Api)?;
+        url.query_pairs_mut().extend_pairs(params);
+
+        self.get_all(url).await.map_err(|e| match (e, kind) {
+            (ApiError::NotFound, TargetKind::Organisation) => FetchReposError::OrganisationNotFound,
+            (ApiError::NotFound, _) => FetchReposError::UserNotFound,
+            (e, _) => FetchReposError::Api(e),
+        })
+    }
+
+    /// Fetches the members of the organisation `org`.
+    pub async fn fetch_members(&self, org: &str) -> Result<Vec<GhUser>, ApiError> {
+        self.get_all(self.url(&format!("/orgs/{}/members", org))?).await
+    }
+
+    /// Fetches the gists of the user `login`. Pass `None` for the authenticated user, whose secret gists are
+    /// included then.
+    pub async fn fetch_gists(&self, login: Option<&str>) -> Result<Vec<GhGist>, ApiError> {
+        let path = match login {
+            Some(login) => format!("/users/{}/gists", login),
+            None => "/gists".to_string(),
+        };
+        self.get_all(self.url(&path)?).await
+    }
+}
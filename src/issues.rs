@@ -0,0 +1,76 @@
+use std::path::Path;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::export::{read_json, write_json, ExportError};
+use crate::github::GitHub;
+
+/// Version of the layout below `issues/`. Exports with a different version are fetched again from scratch.
+const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct IssuesState {
+    version: u32,
+    /// Latest `updated_at` of all exported issues, used as `since` in the next run.
+    since: Option<String>,
+}
+
+/// Exports issues with their comments, labels and milestones of the repository `full_name` to `dir`.
+///
+/// Every issue is stored as `<number>.json` together with its comments. Only issues which were updated since the
+/// last export are fetched again. Pull requests are issues as well for GitHub, so their conversation comments are
+/// exported too.
+pub async fn export_issues(github: &GitHub, full_name: &str, dir: &Path) -> Result<(), ExportError> {
+    let state_path = dir.join("state.json");
+    let since = read_json::<IssuesState>(&state_path)
+        .filter(|state| state.version == LAYOUT_VERSION)
+        .and_then(|state| state.since);
+
+    let mut url = github.url(&format!("/repos/{}/issues", full_name))?;
+    url.query_pairs_mut()
+        .append_pair("state", "all")
+        .append_pair("sort", "updated")
+        .append_pair("direction", "asc");
+    if let Some(since) = &since {
+        url.query_pairs_mut().append_pair("since", since);
+    }
+
+    let issues: Vec<Value> = github.get_all(url).await?;
+
+    let mut latest = since;
+    for issue in issues {
+        let number = issue["number"].as_u64().unwrap_or_default();
+
+        let comments: Vec<Value> = if issue["comments"].as_u64().unwrap_or_default() > 0 {
+            let url = github.url(&format!("/repos/{}/issues/{}/comments", full_name, number))?;
+            github.get_all(url).await?
+        } else {
+            vec![]
+        };
+
+        write_json(&dir.join(format!("{}.json", number)), &json!({
+            "issue": issue,
+            "comments": comments,
+        }))?;
+
+        if let Some(updated_at) = issue["updated_at"].as_str() {
+            if latest.as_deref().is_none_or(|latest| updated_at > latest) {
+                latest = Some(updated_at.to_string());
+            }
+        }
+    }
+
+    let labels: Vec<Value> = github.get_all(github.url(&format!("/repos/{}/labels", full_name))?).await?;
+    write_json(&dir.join("labels.json"), &labels)?;
+
+    let mut url = github.url(&format!("/repos/{}/milestones", full_name))?;
+    url.query_pairs_mut().append_pair("state", "all");
+    let milestones: Vec<Value> = github.get_all(url).await?;
+    write_json(&dir.join("milestones.json"), &milestones)?;
+
+    write_json(&state_path, &IssuesState {
+        version: LAYOUT_VERSION,
+        since: latest,
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use git2::{ObjectType, Oid, Repository, Tree};
+use reqwest::{Client, Response, Url};
+use serde_derive::{Deserialize, Serialize};
+use crate::export::{download_verified, ExportError};
+use crate::github::ApiError;
+
+/// Pointer files are small, larger blobs do not need to be read.
+const MAX_POINTER_SIZE: usize = 1024;
+
+/// Number of objects requested per call of the batch API.
+const BATCH_SIZE: usize = 100;
+
+const LFS_MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+#[derive(Clone, Serialize)]
+struct Pointer {
+    oid: String,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    operation: &'static str,
+    transfers: [&'static str; 1],
+    objects: &'a [Pointer],
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    oid: String,
+    size: u64,
+    actions: Option<BatchActions>,
+    error: Option<BatchError>,
+}
+
+#[derive(Deserialize)]
+struct BatchActions {
+    download: Option<BatchAction>,
+}
+
+#[derive(Deserialize)]
+struct BatchAction {
+    href: String,
+    #[serde(default)]
+    header: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct BatchError {
+    message: String,
+}
+
+/// Parses a Git LFS pointer file.
+fn parse_pointer(content: &[u8]) -> Option<Pointer> {
+    let content = std::str::from_utf8(content).ok()?;
+    if !content.starts_with("version https://git-lfs.github.com/spec/") {
+        return None;
+    }
+
+    let oid = content.lines().find_map(|line| line.strip_prefix("oid sha256:"))?;
+    let size = content.lines().find_map(|line| line.strip_prefix("size "))?.parse().ok()?;
+    if oid.len() != 64 || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(Pointer { oid: oid.to_string(), size })
+}
+
+/// Returns whether the root `.gitattributes` of `tree` routes any path through the LFS filter.
+fn uses_lfs(repo: &Repository, tree: &Tree) -> bool {
+    tree.get_name(".gitattributes")
+        .and_then(|entry| repo.find_blob(entry.id()).ok())
+        .is_some_and(|blob| String::from_utf8_lossy(blob.content()).contains("filter=lfs"))
+}
+
+fn collect_pointers(
+    repo: &Repository,
+    tree: &Tree,
+    seen: &mut HashSet<Oid>,
+    pointers: &mut HashMap<String, Pointer>,
+) -> Result<(), git2::Error> {
+    for entry in tree.iter() {
+        if !seen.insert(entry.id()) {
+            continue;
+        }
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => collect_pointers(repo, &repo.find_tree(entry.id())?, seen, pointers)?,
+            Some(ObjectType::Blob) => {
+                let (size, _) = repo.odb()?.read_header(entry.id())?;
+                if size <= MAX_POINTER_SIZE {
+                    if let Some(pointer) = parse_pointer(repo.find_blob(entry.id())?.content()) {
+                        pointers.insert(pointer.oid.clone(), pointer);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the LFS endpoint configured as `lfs.url` in the root `.lfsconfig` of `tree`, if any.
+fn configured_endpoint(repo: &Repository, tree: &Tree) -> Option<String> {
+    let blob = repo.find_blob(tree.get_name(".lfsconfig")?.id()).ok()?;
+    let content = String::from_utf8_lossy(blob.content());
+
+    let mut in_lfs_section = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_lfs_section = line.trim_matches(|c| c == '[' || c == ']').trim().eq_ignore_ascii_case("lfs");
+        } else if let Some((key, value)) = line.split_once('=') {
+            if in_lfs_section && key.trim().eq_ignore_ascii_case("url") {
+                return Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+
+    None
+}
+
+/// LFS objects of a mirror which still have to be downloaded.
+struct MissingObjects {
+    /// Directory in which git-lfs keeps the objects of the repository.
+    objects_dir: PathBuf,
+    /// Endpoint of the LFS server if the default branch overrides it in its `.lfsconfig`.
+    endpoint: Option<String>,
+    pointers: Vec<Pointer>,
+}
+
+/// Finds the LFS pointers in the trees of all commits reachable from any ref of the repository at `repo_dir`.
+///
+/// Returns the pointers whose objects are missing, or `None` if the repository does not use LFS.
+fn find_missing_objects(repo_dir: &Path) -> Result<Option<MissingObjects>, git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+
+    let mut tips = vec![];
+    for reference in repo.references()? {
+        if let Ok(commit) = reference?.peel_to_commit() {
+            tips.push(commit);
+        }
+    }
+    if !tips.iter().any(|commit| commit.tree().is_ok_and(|tree| uses_lfs(&repo, &tree))) {
+        return Ok(None);
+    }
+
+    // Only the default branch is trusted. Anyone can set the tips of `refs/pull/*` by opening a pull request.
+    let endpoint = repo.head()
+        .and_then(|head| head.peel_to_tree())
+        .ok()
+        .and_then(|tree| configured_endpoint(&repo, &tree));
+
+    let mut revwalk = repo.revwalk()?;
+    for commit in &tips {
+        revwalk.push(commit.id())?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut pointers = HashMap::new();
+    for oid in revwalk {
+        let tree = repo.find_commit(oid?)?.tree()?;
+        if seen.insert(tree.id()) {
+            collect_pointers(&repo, &tree, &mut seen, &mut pointers)?;
+        }
+    }
+
+    let objects_dir = repo.path().join("lfs").join("objects");
+    let pointers = pointers.into_values()
+        .filter(|pointer| fs::metadata(object_path(&objects_dir, &pointer.oid)).map_or(true, |m| m.len() != pointer.size))
+        .collect();
+
+    Ok(Some(MissingObjects { objects_dir, endpoint, pointers }))
+}
+
+/// Returns the path of an object in the layout used by git-lfs.
+fn object_path(objects_dir: &Path, oid: &str) -> PathBuf {
+    objects_dir.join(&oid[0..2]).join(&oid[2..4]).join(oid)
+}
+
+fn check_status(response: Response) -> Result<Response, ExportError> {
+    let code = response.status();
+    if code.is_success() {
+        Ok(response)
+    } else if code.as_u16() == 401 || code.as_u16() == 403 {
+        Err(ExportError::Api(ApiError::Forbidden))
+    } else if code.as_u16() == 404 {
+        Err(ExportError::Api(ApiError::NotFound))
+    } else if code.is_server_error() {
+        Err(ExportError::Api(ApiError::ServerError))
+    } else {
+        Err(ExportError::Api(ApiError::UnknownError))
+    }
+}
+
+async fn download_object(client: &Client, action: &BatchAction, pointer: &Pointer, objects_dir: &Path) -> Result<(), ExportError> {
+    let mut request = client.get(&action.href);
+    for (name, value) in &action.header {
+        request = request.header(name, value);
+    }
+    let response = check_status(request.send().await.map_err(|_| ApiError::UnknownError)?)?;
+
+    let path = object_path(objects_dir, &pointer.oid);
+    fs::create_dir_all(path.parent().unwrap_or(objects_dir))?;
+
+    // The oid of an object is its SHA-256 checksum.
+    download_verified(response, &path, pointer.size, Some(&pointer.oid), &pointer.oid).await?;
+    Ok(())
+}
+
+/// Returns whether `endpoint` is served by the same origin as `clone_url`, which the credentials are meant for.
+fn same_origin(endpoint: &str, clone_url: &str) -> bool {
+    match (Url::parse(endpoint), Url::parse(clone_url)) {
+        (Ok(endpoint), Ok(clone_url)) => {
+            endpoint.scheme() == clone_url.scheme()
+                && endpoint.host_str() == clone_url.host_str()
+                && endpoint.port_or_known_default() == clone_url.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+/// Downloads the LFS objects referenced anywhere in the mirror at `repo_dir` into its `lfs/objects` directory,
+/// where git-lfs expects them. Objects which are already present are skipped.
+///
+/// Like git-lfs, the objects are requested from `<clone_url>/info/lfs` unless `.lfsconfig` of the default branch
+/// sets another `lfs.url`. The credentials are only sent to an endpoint on the host of `clone_url`.
+///
+/// Objects which the server reports errors for are skipped, the others are still downloaded. These objects are
+/// listed in the returned error. Otherwise, returns the number of downloaded objects.
+pub async fn backup_lfs(client: &Client, repo_dir: &Path, clone_url: &str, username: &str, token: &str) -> Result<usize, ExportError> {
+    let dir = repo_dir.to_path_buf();
+    let missing = tokio::task::spawn_blocking(move || find_missing_objects(&dir)).await
+        .map_err(|e| ExportError::Lfs(e.to_string()))?
+        .map_err(ExportError::Git)?;
+
+    let Some(missing) = missing else {
+        return Ok(0);
+    };
+
+    let endpoint = missing.endpoint.unwrap_or_else(|| format!("{}/info/lfs", clone_url.trim_end_matches('/')));
+    let batch_url = format!("{}/objects/batch", endpoint.trim_end_matches('/'));
+    let authenticate = same_origin(&endpoint, clone_url);
+    let mut downloaded = 0;
+    let mut failed = vec![];
+    for pointers in missing.pointers.chunks(BATCH_SIZE) {
+        let mut request = client.post(&batch_url)
+            .header("Accept", LFS_MEDIA_TYPE)
+            .header("Content-Type", LFS_MEDIA_TYPE)
+            .header("User-Agent", "request");
+        if authenticate {
+            request = request.basic_auth(username, Some(token));
+        }
+
+        let response = request
+            .json(&BatchRequest {
+                operation: "download",
+                transfers: ["basic"],
+                objects: pointers,
+            })
+            .send().await
+            .map_err(|_| ApiError::UnknownError)?;
+
+        let batch: BatchResponse = check_status(response)?
+            .json().await
+            .map_err(|_| ApiError::UnknownError)?;
+
+        for object in batch.objects {
+            // E.g. a pointer whose object was never pushed. This must not keep the other objects from being backed up.
+            if let Some(error) = object.error {
+                failed.push(format!("{}: {}", object.oid, error.message));
+                continue;
+            }
+
+            let Some(action) = object.actions.and_then(|actions| actions.download) else {
+                continue;
+            };
+
+            let pointer = Pointer { oid: object.oid, size: object.size };
+            if pointer.oid.len() != 64 || !pointers.iter().any(|requested| requested.oid == pointer.oid) {
+                return Err(ExportError::Lfs(format!("unexpected object {}", pointer.oid)));
+            }
+
+            download_object(client, &action, &pointer, &missing.objects_dir).await?;
+            downloaded += 1;
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(ExportError::Lfs(format!("{} objects are not available: {}", failed.len(), failed.join(", "))));
+    }
+
+    Ok(downloaded)
+}
@@ -1,183 +1,242 @@
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
+mod backup;
+mod config;
+mod export;
+mod filter;
+mod gists;
+mod github;
+mod issues;
+mod lfs;
+mod manifest;
+mod metadata;
+mod mirror;
+mod organisation;
+mod plan;
+mod relocate;
+mod pulls;
+mod releases;
+mod report;
+mod retry;
+mod state;
+
+use std::collections::BTreeMap;
 use std::fs;
-use std::future::Future;
-use std::path::PathBuf;
+use std::path::{absolute, PathBuf};
 use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use argh::FromArgs;
-use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{FetchOptions, Repository};
-use reqwest::{Client, StatusCode, Url};
-use serde_derive::Deserialize;
-use futures_util::{stream, StreamExt};
+use reqwest::{Client, Url};
+use crate::backup::{backup_all, Backup, BackupJob, Context, Job};
+use crate::config::{BackupOptions, Config, Target};
+use crate::export::{reserved_dir, RESERVED_DIR};
+use crate::filter::{ArchivedFilter, RepoFilter};
+use crate::gists::{fetch_target_gists, GistJob};
+use crate::manifest::write_manifest;
+use crate::github::{GitHub, TargetKind, DEFAULT_API_URL};
+use crate::mirror::repo_dir;
+use crate::organisation::export_organisation;
+use crate::plan::print_plan;
+use crate::relocate::relocate_backups;
+use crate::report::{exit_code, print_summary, RepoReport};
+use crate::retry::DEFAULT_RETRIES;
+use crate::state::BackupState;
 
 #[derive(FromArgs)]
-#[argh(description = "Tool for creating backups from Github organisations")]
+#[argh(description = "Tool for creating backups from Github organisations and users")]
 struct GhBackup {
     #[argh(switch, short = 'd')]
     #[argh(description = "perform a dry run. No data will be persisted.")]
     dry: bool,
 
-    #[argh(option)]
-    #[argh(description = "optional path to the backup directory. Defaults to: ./organisation-backup")]
-    backup_dir: Option<PathBuf>,
-
-    #[argh(positional)]
-    #[argh(description = "name of the github organisation.")]
-    organisation: String,
-}
+    #[argh(option, short = 'c')]
+    #[argh(description = "path to a TOML config file listing several backup targets.")]
+    config: Option<PathBuf>,
 
-#[derive(Deserialize)]
-struct GhRepo {
-    name: String,
-    full_name: String,
-    clone_url: String,
-}
+    #[argh(option)]
+    #[argh(description = "base URL of the GitHub API, e.g. https://ghe.example.com/api/v3 for GitHub Enterprise Server. Defaults to: https://api.github.com")]
+    api_url: Option<String>,
 
-#[derive(Deserialize)]
-struct GhUser {
-    login: String,
-}
+    #[argh(option, short = 'j')]
+    #[argh(description = "maximum number of repositories backed up in parallel. Defaults to: 10")]
+    jobs: Option<usize>,
 
-enum FetchReposError {
-    OrganisationNotFound,
-    Forbidden,
-    ServerError,
-    UnknownError,
-}
+    #[argh(option)]
+    #[argh(description = "number of retries for transient network and server errors. Defaults to: 3")]
+    retries: Option<u32>,
 
-impl Debug for FetchReposError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FetchReposError::OrganisationNotFound => write!(f, "Organisation not found."),
-            FetchReposError::Forbidden => write!(f, "Access forbidden."),
-            FetchReposError::ServerError => write!(f, "Server error."),
-            FetchReposError::UnknownError => write!(f, "Unknown error.")
-        }
-    }
-}
+    #[argh(switch)]
+    #[argh(description = "store repositories as bare mirrors named <name>.git, like `git clone --mirror` does.")]
+    bare: bool,
 
-impl Display for FetchReposError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
+    #[argh(switch)]
+    #[argh(description = "also back up the wikis of repositories next to them as <name>.wiki.")]
+    wiki: bool,
 
+    #[argh(switch)]
+    #[argh(description = "also export issues with their comments, labels and milestones as JSON.")]
+    issues: bool,
 
-enum UserError {
-    Forbidden,
-    ServerError,
-    UnknownError,
-}
+    #[argh(switch)]
+    #[argh(description = "also export pull requests with their reviews and review comments as JSON.")]
+    pulls: bool,
 
-impl Debug for UserError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            UserError::Forbidden => write!(f, "Access forbidden."),
-            UserError::ServerError => write!(f, "Server error."),
-            UserError::UnknownError => write!(f, "Unknown error.")
-        }
-    }
-}
+    #[argh(switch)]
+    #[argh(description = "also download releases with their assets.")]
+    releases: bool,
 
-impl Display for UserError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
+    #[argh(switch)]
+    #[argh(description = "also download the Git LFS objects referenced by repositories into their lfs/objects directory.")]
+    lfs: bool,
 
-impl Error for UserError {}
+    #[argh(switch)]
+    #[argh(description = "also back up gists with their comments to .gh-backup/gists/<login>/<id>. For organisations, these are the gists of all members.")]
+    gists: bool,
 
-const MAX_PAGE: usize = 1000;
+    #[argh(switch)]
+    #[argh(description = "also save the settings and metadata of repositories as JSON and record their changes.")]
+    metadata: bool,
 
-async fn fetch_user(gh_token: &str) -> Result<GhUser, UserError> {
-    let client = Client::new();
-    let url = Url::parse(
-        "https://api.github.com/user",
-    ).map_err(|e| UserError::UnknownError)?;
+    #[argh(switch)]
+    #[argh(description = "also save members, outside collaborators and teams with their repository permissions of the organisation and record their changes.")]
+    organisation: bool,
 
-    let response = client.get(url.as_str())
-        .header("Accept", "application/vnd.github+json")
-        .header("User-Agent",  "request")
-        .bearer_auth(&gh_token)
-        .send().await.map_err(|e| UserError::UnknownError)?;
+    #[argh(option)]
+    #[argh(description = "glob pattern matched against the name and full name of repositories to back up. Can be repeated.")]
+    include: Vec<String>,
 
-    let code = response.status();
-    if code == StatusCode::FORBIDDEN {
-        return Err(UserError::Forbidden);
-    }
+    #[argh(option)]
+    #[argh(description = "glob pattern matched against the name and full name of repositories to leave out. Can be repeated.")]
+    exclude: Vec<String>,
 
-    if code.is_server_error() {
-        return Err(UserError::ServerError);
-    }
+    #[argh(switch)]
+    #[argh(description = "leave out forks.")]
+    no_forks: bool,
 
-    if !code.is_success() {
-        return Err(UserError::UnknownError);
-    }
+    #[argh(option)]
+    #[argh(description = "whether to skip archived repositories, back up only them or include them. Defaults to: include")]
+    archived: Option<ArchivedFilter>,
 
-    let user: GhUser = response
-        .json().await
-        .map_err(|e| UserError::UnknownError)?;
+    #[argh(option)]
+    #[argh(description = "comma separated visibilities of the repositories to back up, e.g. private,internal.")]
+    visibility: Option<String>,
 
-    Ok(user)
-}
-async fn fetch_repos(organisation: &str, gh_token:  &str) -> Result<Vec<GhRepo>, FetchReposError> {
-    let mut repos = vec![];
-
-    for page in 1..MAX_PAGE {
-        let client = Client::new();
-        let url = Url::parse_with_params(
-            format!("https://api.github.com/orgs/{}/repos", organisation).as_str(),
-            &[("page", page.to_string().as_str()), ("type", "all")],
-        ).map_err(|e| FetchReposError::UnknownError)?;
-
-        let response = client.get(url.as_str())
-            .header("Accept", "application/vnd.github+json")
-            .header("User-Agent",  "request")
-            .bearer_auth(&gh_token)
-            .send().await.map_err(|e| FetchReposError::UnknownError)?;
-
-        let code = response.status();
-
-        if code == StatusCode::NOT_FOUND {
-            return Err(FetchReposError::OrganisationNotFound);
-        }
+    #[argh(option)]
+    #[argh(description = "leave out repositories larger than this size in MiB.")]
+    max_size: Option<u64>,
 
-        if code == StatusCode::FORBIDDEN {
-            return Err(FetchReposError::Forbidden);
-        }
+    #[argh(option)]
+    #[argh(description = "back up only repositories with this topic. Can be repeated to allow several topics.")]
+    topic: Vec<String>,
 
-        if code.is_server_error() {
-            return Err(FetchReposError::ServerError);
-        }
+    #[argh(switch)]
+    #[argh(description = "fetch all repositories, including those which did not change since the last backup.")]
+    full: bool,
 
-        if !code.is_success() {
-            return Err(FetchReposError::UnknownError);
-        }
+    #[argh(option)]
+    #[argh(description = "optional path to the backup directory. Defaults to: ./<name>_backup")]
+    backup_dir: Option<PathBuf>,
 
-        let mut response_repos: Vec<GhRepo> = response
-            .json().await
-            .map_err(|e| FetchReposError::UnknownError)?;
+    #[argh(option)]
+    #[argh(description = "kind of the backup target: org, user or me for the authenticated user. Defaults to: org")]
+    kind: Option<TargetKind>,
 
-        if response_repos.is_empty() {
-            break;
-        }
+    #[argh(positional)]
+    #[argh(description = "name of the github organisation or user. Omit it for --kind me.")]
+    name: Option<String>,
+}
 
-        repos.append(&mut response_repos);
+impl GhBackup {
+    /// Returns whether any option which only applies to the single target given on the command line is set.
+    fn has_target_options(&self) -> bool {
+        self.bare || self.wiki || self.issues || self.pulls || self.releases || self.lfs || self.gists
+            || self.metadata || self.organisation || !self.include.is_empty() || !self.exclude.is_empty()
+            || self.no_forks || self.archived.is_some() || self.visibility.is_some() || self.max_size.is_some()
+            || !self.topic.is_empty() || self.backup_dir.is_some() || self.kind.is_some()
     }
-
-    Ok(repos)
 }
 
+const DEFAULT_JOBS: usize = 10;
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> ExitCode {
+    let started_at = SystemTime::now();
     let cli: GhBackup = argh::from_env();
 
-    let organisation = cli.organisation;
-    let backup_dir = cli.backup_dir.unwrap_or(format!("{}_backup", organisation).into());
+    let config = match &cli.config {
+        Some(path) => {
+            if cli.name.is_some() {
+                eprintln!("The target name cannot be combined with --config.");
+                return ExitCode::FAILURE;
+            }
+
+            // Otherwise they would be silently ignored.
+            if cli.has_target_options() {
+                eprintln!("Options of a target cannot be combined with --config. Set them in the config file instead.");
+                return ExitCode::FAILURE;
+            }
+
+            match config::load(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => Config {
+            api_url: None,
+            jobs: None,
+            retries: None,
+            targets: vec![Target {
+                kind: cli.kind.unwrap_or_default(),
+                name: cli.name,
+                backup_dir: cli.backup_dir,
+                options: BackupOptions {
+                    bare: cli.bare,
+                    wiki: cli.wiki,
+                    issues: cli.issues,
+                    pulls: cli.pulls,
+                    releases: cli.releases,
+                    lfs: cli.lfs,
+                    gists: cli.gists,
+                    metadata: cli.metadata,
+                    organisation: cli.organisation,
+                    include: cli.include,
+                    exclude: cli.exclude,
+                    no_forks: cli.no_forks,
+                    archived: cli.archived.unwrap_or_default(),
+                    visibility: cli.visibility
+                        .map(|visibility| visibility.split(',').map(|v| v.trim().to_string()).collect())
+                        .unwrap_or_default(),
+                    max_size: cli.max_size,
+                    topics: cli.topic,
+                },
+                unknown: BTreeMap::new(),
+            }],
+        },
+    };
+    let targets = config.targets;
 
-    if backup_dir.exists() {
-        eprintln!("Backup directory {} does already exist", backup_dir.display());
+    // Options given on the command line take precedence over the config file.
+    let api_url = cli.api_url.or(config.api_url).unwrap_or(DEFAULT_API_URL.to_string());
+    if let Err(e) = Url::parse(&api_url) {
+        eprintln!("Invalid API URL {}: {}", api_url, e);
+        return ExitCode::FAILURE;
+    }
+
+    let jobs = cli.jobs.or(config.jobs).unwrap_or(DEFAULT_JOBS);
+    if jobs == 0 {
+        eprintln!("The number of jobs must be at least 1.");
+        return ExitCode::FAILURE;
+    }
+
+    let retries = cli.retries.or(config.retries).unwrap_or(DEFAULT_RETRIES);
+
+    for target in &targets {
+        if let Err(e) = target.check() {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
     }
 
     let Ok(gh_token) = std::env::var("GH_TOKEN").or(std::env::var("GITHUB_TOKEN")) else {
@@ -185,76 +244,167 @@ async fn main() -> ExitCode {
         return ExitCode::FAILURE;
     };
 
+    let github = GitHub::new(Client::new(), &api_url, &gh_token, retries);
+
     println!("Getting user info");
-    let user = match fetch_user(&gh_token).await {
+    let user = match github.fetch_user().await {
         Ok(user) => user,
         Err(e) => {
-            eprintln!("Failed to fetch repos: {}", e);
+            eprintln!("Failed to fetch user: {}", e);
             return ExitCode::FAILURE;
         }
     };
 
-    println!("Getting repos");
-    let repos = match fetch_repos(&organisation, &gh_token).await {
-        Ok(repos) => repos,
-        Err(e) => {
-            eprintln!("Failed to fetch repos: {}", e);
+    let ctx = Arc::new(Context {
+        github,
+        username: user.login,
+        token: gh_token,
+        retries,
+        full: cli.full,
+    });
+
+    let mut failed_targets = vec![];
+    let mut queue = vec![];
+    let mut gist_queue = vec![];
+    let mut backups = vec![];
+    let mut resolved: Vec<(Target, String, PathBuf)> = vec![];
+    for mut target in targets {
+        let name = target.name.take().unwrap_or_else(|| ctx.username.clone());
+        let backup_dir = target.backup_dir.take().unwrap_or(format!("{}_backup", name).into());
+
+        // Targets sharing a backup directory would overwrite each other's state and take each other's repositories
+        // for orphans.
+        let absolute_dir = absolute(&backup_dir).unwrap_or(backup_dir.clone());
+        if resolved.iter().any(|(_, _, other)| absolute(other).unwrap_or(other.clone()) == absolute_dir) {
+            eprintln!("Several targets share the backup directory {}.", backup_dir.display());
             return ExitCode::FAILURE;
         }
-    };
+        resolved.push((target, name, backup_dir));
+    }
 
-    if let Err(e) = fs::create_dir_all(&backup_dir) {
-        eprintln!("Failed to create backup directory: {}", e);
-        return ExitCode::FAILURE;
-    };
+    for (target, name, backup_dir) in resolved {
+        if backup_dir.exists() {
+            eprintln!("Backup directory {} does already exist", backup_dir.display());
+        }
 
-    let handles: Vec<_> = repos
-        .into_iter()
-        .map(|repo| {
-            let backup_dir = backup_dir.clone();
-            let username = user.login.clone();
-            let gh_token = gh_token.clone();
-            tokio::task::spawn(
-            async move {
-                println!("Started to backup: {} from {}", repo.full_name, repo.clone_url);
-
-                let mut builder = CheckoutBuilder::new();
-                builder.dry_run();
-
-                let mut cb = git2::RemoteCallbacks::new();
-                cb.credentials(|a, b, c| git2::Cred::userpass_plaintext(&username, &gh_token));
-
-                let mut fo = FetchOptions::new();
-                fo.remote_callbacks(cb)
-                    .download_tags(git2::AutotagOption::All)
-                    .update_fetchhead(true);
-
-                let repo_dir = backup_dir.join(repo.name);
-                if repo_dir.exists() {
-                    let repo = Repository::open(repo_dir).unwrap();
-                    for remote_name in repo.remotes().unwrap().iter() {
-
-                        repo.find_remote(remote_name.unwrap()).unwrap().download(&[] as &[&str], Some(&mut fo)).unwrap();
-                    };
-                } else {
-                    match RepoBuilder::new()
-                        .fetch_options(fo)
-                        .with_checkout(builder)
-                        .clone(&repo.clone_url, repo_dir.as_path()) {
-                        Ok(_repo) => ExitCode::SUCCESS,
-                        Err(e) => {
-                            eprintln!("Failed to clone: {}", e);
-                            ExitCode::FAILURE
-                        }
-                    };
+        let filter = match RepoFilter::new(&target.options, &backup_dir) {
+            Ok(filter) => filter,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed_targets.push(name);
+                continue;
+            }
+        };
+
+        println!("Getting repos of {}", name);
+        let mut repos = match ctx.github.fetch_repos(target.kind, &name).await {
+            Ok(repos) => repos,
+            Err(e) => {
+                eprintln!("Failed to fetch repos of {}: {}", name, e);
+                failed_targets.push(name);
+                continue;
+            }
+        };
+
+        let mut state = BackupState::load(&backup_dir);
+        relocate_backups(&backup_dir, target.kind, target.options.bare, &repos, &mut state, cli.dry);
+
+        let listed = repos.len();
+        repos.retain(|repo| filter.matches(repo));
+        if repos.len() < listed {
+            println!("Leaving out {} repos of {} due to filters", listed - repos.len(), name);
+        }
+
+        let reserved = reserved_dir(&backup_dir);
+        if let Some(index) = repos.iter()
+            .position(|repo| repo_dir(&backup_dir, target.kind.repo_path(repo), target.options.bare) == reserved)
+        {
+            let repo = repos.remove(index);
+            eprintln!("Cannot back up {}, as {} is reserved for gh-backup. It can be backed up in the bare layout.",
+                      repo.full_name, RESERVED_DIR);
+            failed_targets.push(name.clone());
+        }
+
+        let mut gists = vec![];
+        if target.options.gists {
+            println!("Getting gists of {}", name);
+            match fetch_target_gists(&ctx.github, target.kind, &name).await {
+                Ok(target_gists) => gists = target_gists,
+                Err(e) => {
+                    eprintln!("Failed to fetch gists of {}: {}", name, e);
+                    failed_targets.push(name.clone());
                 }
-            })
-        })
+            }
+        }
+
+        if cli.dry {
+            let plan_state = if cli.full { &BackupState::default() } else { &state };
+            print_plan(&backup_dir, &repos, target.kind, target.options.bare, plan_state);
+            if target.options.gists {
+                println!("Would back up {} gists.", gists.len());
+            }
+            continue;
+        }
+
+        if let Err(e) = fs::create_dir_all(&backup_dir) {
+            eprintln!("Failed to create backup directory: {}", e);
+            failed_targets.push(name);
+            continue;
+        };
+
+        if target.options.organisation {
+            println!("Getting structure of {}", name);
+            if let Err(e) = export_organisation(&ctx.github, &name, &backup_dir).await {
+                eprintln!("Failed to export structure of {}: {}", name, e);
+                failed_targets.push(name.clone());
+            }
+        }
+
+        let backup = Arc::new(Backup {
+            kind: target.kind,
+            backup_dir,
+            options: target.options,
+            state: Mutex::new(state),
+        });
+        backups.push(backup.clone());
+        queue.extend(repos.into_iter().map(|repo| Arc::new(Job { backup: backup.clone(), repo })));
+        gist_queue.extend(gists.into_iter().map(|(owner, gist)| Arc::new(GistJob { backup: backup.clone(), owner, gist })));
+    }
+
+    let results = backup_all(&ctx, queue, jobs).await;
+    let gist_results = backup_all(&ctx, gist_queue, jobs).await;
+
+    for backup in &backups {
+        if let Ok(state) = backup.state.lock() {
+            if let Err(e) = state.save(&backup.backup_dir) {
+                eprintln!("Failed to save state of {}: {}", backup.backup_dir.display(), e);
+            }
+        }
+
+        let backup_results: Vec<_> = results.iter()
+            .filter(|(job, _, _)| Arc::ptr_eq(&job.backup, backup))
+            .collect();
+        let backup_gist_results: Vec<_> = gist_results.iter()
+            .filter(|(job, _, _)| Arc::ptr_eq(&job.backup, backup))
+            .collect();
+        if let Err(e) = write_manifest(backup, &backup_results, &backup_gist_results, started_at) {
+            eprintln!("Failed to write manifest of {}: {}", backup.backup_dir.display(), e);
+        }
+    }
+
+    let mut reports: Vec<RepoReport> = results
+        .into_iter()
+        .map(|(job, outcome, _)| RepoReport { full_name: job.name(), outcome })
         .collect();
+    reports.extend(gist_results.into_iter().map(|(job, outcome, _)| RepoReport { full_name: job.name(), outcome }));
 
-    stream::iter(handles)
-        .buffer_unordered(10)
-        .collect::<Vec<_>>().await;
+    if !cli.dry {
+        print_summary(&reports, &failed_targets);
+    }
+
+    if ctx.github.rate_limit_waits() > 0 {
+        println!("Waited {} times for the GitHub API rate limit to reset.", ctx.github.rate_limit_waits());
+    }
 
-    ExitCode::SUCCESS
+    exit_code(&reports, &failed_targets)
 }
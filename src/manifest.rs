@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use git2::Repository;
+use serde_derive::Serialize;
+use crate::backup::{Backup, Job};
+use crate::export::{file_timestamp, reserved_dir, timestamp, write_json};
+use crate::gists::GistJob;
+use crate::mirror::repo_dir;
+use crate::report::Outcome;
+
+/// What a run captured for a single mirror.
+#[derive(Serialize)]
+struct Capture {
+    /// Target of every ref in the mirror after the run.
+    refs: BTreeMap<String, String>,
+    /// Size of the mirror on disk in bytes.
+    size: u64,
+    duration_secs: f64,
+    outcome: String,
+}
+
+impl Capture {
+    fn new(repo_dir: &Path, outcome: &Outcome, duration: &Duration) -> Self {
+        Capture {
+            refs: read_refs(repo_dir),
+            size: dir_size(repo_dir),
+            duration_secs: duration.as_secs_f64(),
+            outcome: outcome.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    id: u64,
+    full_name: String,
+    #[serde(flatten)]
+    capture: Capture,
+}
+
+#[derive(Serialize)]
+struct GistManifestEntry {
+    id: String,
+    owner: String,
+    #[serde(flatten)]
+    capture: Capture,
+}
+
+/// What a run captured for the repositories and gists of a backup directory.
+#[derive(Serialize)]
+struct Manifest {
+    started_at: String,
+    finished_at: String,
+    repos: Vec<ManifestEntry>,
+    gists: Vec<GistManifestEntry>,
+}
+
+fn read_refs(repo_dir: &Path) -> BTreeMap<String, String> {
+    let mut refs = BTreeMap::new();
+    let Ok(repo) = Repository::open(repo_dir) else {
+        return refs;
+    };
+    let Ok(references) = repo.references() else {
+        return refs;
+    };
+
+    for reference in references.flatten() {
+        let target = reference.resolve().ok().and_then(|resolved| resolved.target());
+        if let (Some(name), Some(target)) = (reference.name(), target) {
+            refs.insert(name.to_string(), target.to_string());
+        }
+    }
+    refs
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+
+    entries.flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Writes the manifest of a run to `manifest.json` in the reserved directory and keeps a copy named after the start
+/// of the run in `manifests/` there, so the state of the backup at any date can be looked up.
+pub fn write_manifest(
+    backup: &Backup,
+    results: &[&(Arc<Job>, Outcome, Duration)],
+    gist_results: &[&(Arc<GistJob>, Outcome, Duration)],
+    started_at: SystemTime,
+) -> io::Result<()> {
+    let bare = backup.options.bare;
+    let repos = results.iter()
+        .map(|(job, outcome, duration)| ManifestEntry {
+            id: job.repo.id,
+            full_name: job.repo.full_name.clone(),
+            capture: Capture::new(&repo_dir(&backup.backup_dir, backup.kind.repo_path(&job.repo), bare), outcome, duration),
+        })
+        .collect();
+
+    let gists = gist_results.iter()
+        .map(|(job, outcome, duration)| GistManifestEntry {
+            id: job.gist.id.clone(),
+            owner: job.owner.clone(),
+            capture: Capture::new(&repo_dir(&backup.backup_dir, &job.path(), bare), outcome, duration),
+        })
+        .collect();
+
+    let manifest = Manifest {
+        started_at: timestamp(started_at),
+        finished_at: timestamp(SystemTime::now()),
+        repos,
+        gists,
+    };
+
+    let name = format!("{}.json", file_timestamp(started_at));
+    let dir = reserved_dir(&backup.backup_dir);
+    write_json(&dir.join("manifests").join(name), &manifest)?;
+    write_json(&dir.join("manifest.json"), &manifest)
+}
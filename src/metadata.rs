@@ -0,0 +1,42 @@
+use std::path::Path;
+use serde_json::{Map, Value};
+use crate::export::{changed_fields, read_json, record_changes, write_json, ExportError};
+use crate::github::GitHub;
+
+/// Fields which change with every push, star or issue. They are no settings, so changing them is not recorded.
+const VOLATILE_FIELDS: &[&str] = &[
+    "updated_at",
+    "pushed_at",
+    "size",
+    "stargazers_count",
+    "watchers_count",
+    "watchers",
+    "forks_count",
+    "forks",
+    "open_issues_count",
+    "open_issues",
+    "network_count",
+    "subscribers_count",
+];
+
+fn settings(metadata: &Value) -> Map<String, Value> {
+    let mut settings = metadata.as_object().cloned().unwrap_or_default();
+    settings.retain(|key, _| !VOLATILE_FIELDS.contains(&key.as_str()));
+    settings
+}
+
+/// Saves the full repository object of `full_name` as `metadata.json` in `dir`.
+///
+/// Settings which changed since the previous run are appended to `metadata_history.json`, so former settings can be
+/// restored.
+pub async fn export_metadata(github: &GitHub, full_name: &str, dir: &Path) -> Result<(), ExportError> {
+    let metadata: Value = github.get(&github.url(&format!("/repos/{}", full_name))?).await?;
+
+    let path = dir.join("metadata.json");
+    if let Some(previous) = read_json::<Value>(&path) {
+        record_changes(&dir.join("metadata_history.json"), changed_fields(&settings(&previous), &settings(&metadata)))?;
+    }
+
+    write_json(&path, &metadata)?;
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use git2::{ErrorClass, ErrorCode, FetchOptions, Repository};
+use crate::export::with_suffix;
+use crate::retry;
+
+/// Returns the directory in which the repository at the relative `path` is backed up.
+pub fn repo_dir(backup_dir: &Path, path: &str, bare: bool) -> PathBuf {
+    if bare {
+        backup_dir.join(format!("{}.git", path))
+    } else {
+        backup_dir.join(path)
+    }
+}
+
+pub enum Mirrored {
+    /// The repository did not exist in the backup and was cloned.
+    Cloned,
+    /// The repository already existed in the backup and was updated.
+    Updated,
+    /// The repository did not change since the last backup, so it was not fetched.
+    Unchanged,
+}
+
+pub enum MirrorError {
+    Open(git2::Error),
+    Init(git2::Error),
+    Remote(git2::Error),
+    Fetch(git2::Error),
+    Head(git2::Error),
+}
+
+impl MirrorError {
+    /// Returns whether mirroring may succeed when retried. Only fetching talks to the remote.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MirrorError::Fetch(e) => retry::is_transient(e),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the remote repository does not exist.
+    ///
+    /// The local transport used for `file://` remotes reports a missing repository only by its message.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            MirrorError::Fetch(e) => {
+                e.code() == ErrorCode::NotFound
+                    || (e.class() == ErrorClass::Http && retry::http_status(e) == Some(404))
+                    || (e.class() == ErrorClass::Os && e.message().starts_with("failed to resolve path"))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn git_error(&self) -> &git2::Error {
+        match self {
+            MirrorError::Open(e)
+            | MirrorError::Init(e)
+            | MirrorError::Remote(e)
+            | MirrorError::Fetch(e)
+            | MirrorError::Head(e) => e,
+        }
+    }
+}
+
+impl Debug for MirrorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MirrorError::Open(e) => write!(f, "Failed to open repository: {}", e.message()),
+            MirrorError::Init(e) => write!(f, "Failed to create repository: {}", e.message()),
+            MirrorError::Remote(e) => write!(f, "Failed to configure remote: {}", e.message()),
+            MirrorError::Fetch(e) => write!(f, "Failed to fetch: {}", e.message()),
+            MirrorError::Head(e) => write!(f, "Failed to update HEAD: {}", e.message()),
+        }
+    }
+}
+
+impl Display for MirrorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for MirrorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.git_error())
+    }
+}
+
+/// Mirrors every ref, including `refs/pull/*` which keeps the commits of pull requests after their branches
+/// were deleted.
+const MIRROR_REFSPEC: &str = "+refs/*:refs/*";
+
+/// Configures the `origin` remote of `repo` as mirror of `url`, fetches all refs and updates HEAD.
+fn fetch_mirror(repo: &Repository, url: &str, fo: &mut FetchOptions) -> Result<(), MirrorError> {
+    let mut remote = match repo.find_remote("origin") {
+        Ok(remote) if remote.url() == Some(url) => remote,
+        Ok(_) => {
+            // Follow the clone URL reported by the API, e.g. after a repository or host was renamed.
+            repo.remote_set_url("origin", url).map_err(MirrorError::Remote)?;
+            repo.find_remote("origin").map_err(MirrorError::Remote)?
+        }
+        Err(_) => {
+            let remote = repo.remote_with_fetch("origin", url, MIRROR_REFSPEC).map_err(MirrorError::Remote)?;
+            repo.config()
+                .and_then(|mut config| config.set_bool("remote.origin.mirror", true))
+                .map_err(MirrorError::Remote)?;
+            remote
+        }
+    };
+
+    remote.fetch(&[MIRROR_REFSPEC], Some(fo), None).map_err(MirrorError::Fetch)?;
+
+    if let Ok(head) = remote.default_branch() {
+        if let Some(head) = head.as_str() {
+            repo.set_head(head).map_err(MirrorError::Head)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mirrors all refs of the remote at `url` into the repository at `repo_dir`.
+///
+/// The repository is created if it does not exist yet, as a bare repository if `bare` is set. A new repository is
+/// only moved to `repo_dir` once the first fetch succeeded, so a failed clone leaves nothing behind which later runs
+/// would take for a mirror. Refs are force-updated and refs which were deleted on the remote are pruned, like
+/// `git fetch --mirror` does.
+pub fn mirror_repo(repo_dir: &Path, url: &str, bare: bool, fo: &mut FetchOptions) -> Result<Mirrored, MirrorError> {
+    if repo_dir.exists() {
+        let repo = Repository::open(repo_dir).map_err(MirrorError::Open)?;
+        fetch_mirror(&repo, url, fo)?;
+        return Ok(Mirrored::Updated);
+    }
+
+    // Left behind if a previous run was killed while cloning.
+    let partial = with_suffix(repo_dir, ".partial");
+    let _ = fs::remove_dir_all(&partial);
+
+    let result = if bare { Repository::init_bare(&partial) } else { Repository::init(&partial) }
+        .map_err(MirrorError::Init)
+        .and_then(|repo| fetch_mirror(&repo, url, fo))
+        .and_then(|()| fs::rename(&partial, repo_dir).map_err(|e| MirrorError::Init(git2::Error::from_str(&e.to_string()))));
+
+    match result {
+        Ok(()) => Ok(Mirrored::Cloned),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&partial);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::export::{changed_fields, read_json, record_changes, reserved_dir, write_json, ExportError};
+use crate::github::{ApiError, GhUser, GitHub};
+
+/// Repository roles from the most to the least privileged one.
+const ROLES: [&str; 5] = ["admin", "maintain", "push", "triage", "pull"];
+
+#[derive(Deserialize)]
+struct GhTeamParent {
+    slug: String,
+}
+
+#[derive(Deserialize)]
+struct GhTeam {
+    slug: String,
+    name: String,
+    privacy: Option<String>,
+    parent: Option<GhTeamParent>,
+}
+
+#[derive(Deserialize)]
+struct GhTeamRepo {
+    full_name: String,
+    role_name: Option<String>,
+    #[serde(default)]
+    permissions: BTreeMap<String, bool>,
+}
+
+impl GhTeamRepo {
+    /// Returns the role of the team, falling back to the most privileged permission on servers without role names.
+    fn role(&self) -> String {
+        self.role_name.clone().unwrap_or_else(|| {
+            let role = ROLES.iter().find(|role| self.permissions.get(**role).copied().unwrap_or_default());
+            role.unwrap_or(&"none").to_string()
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TeamSnapshot {
+    name: String,
+    parent: Option<String>,
+    privacy: Option<String>,
+    /// Role of each member by login.
+    members: BTreeMap<String, String>,
+    /// Role of the team by repository.
+    repos: BTreeMap<String, String>,
+}
+
+/// Who has access to what in an organisation.
+#[derive(Serialize, Deserialize)]
+struct OrganisationSnapshot {
+    /// Role of each member by login.
+    members: BTreeMap<String, String>,
+    outside_collaborators: BTreeSet<String>,
+    /// Teams by slug.
+    teams: BTreeMap<String, TeamSnapshot>,
+}
+
+impl OrganisationSnapshot {
+    /// Returns every permission as a separate entry like `team/<slug>/repo/<full_name>`, so changes can be listed
+    /// one by one.
+    fn entries(&self) -> Map<String, Value> {
+        let mut entries = Map::new();
+        for (login, role) in &self.members {
+            entries.insert(format!("member/{}", login), role.clone().into());
+        }
+        for login in &self.outside_collaborators {
+            entries.insert(format!("outside_collaborator/{}", login), true.into());
+        }
+        for (slug, team) in &self.teams {
+            entries.insert(format!("team/{}/name", slug), team.name.clone().into());
+            entries.insert(format!("team/{}/parent", slug), team.parent.clone().into());
+            entries.insert(format!("team/{}/privacy", slug), team.privacy.clone().into());
+            for (login, role) in &team.members {
+                entries.insert(format!("team/{}/member/{}", slug, login), role.clone().into());
+            }
+            for (repo, role) in &team.repos {
+                entries.insert(format!("team/{}/repo/{}", slug, repo), role.clone().into());
+            }
+        }
+        entries
+    }
+}
+
+/// Fetches the logins of the members of `path` with each role in `roles`.
+async fn fetch_roles(github: &GitHub, path: &str, roles: &[&str]) -> Result<BTreeMap<String, String>, ApiError> {
+    let mut members = BTreeMap::new();
+    for role in roles {
+        let mut url = github.url(path)?;
+        url.query_pairs_mut().append_pair("role", role);
+
+        let users: Vec<GhUser> = github.get_all(url).await?;
+        members.extend(users.into_iter().map(|user| (user.login, role.to_string())));
+    }
+    Ok(members)
+}
+
+async fn fetch_snapshot(github: &GitHub, org: &str) -> Result<OrganisationSnapshot, ApiError> {
+    let members = fetch_roles(github, &format!("/orgs/{}/members", org), &["admin", "member"]).await?;
+
+    let collaborators: Vec<GhUser> = github.get_all(github.url(&format!("/orgs/{}/outside_collaborators", org))?).await?;
+    let outside_collaborators = collaborators.into_iter().map(|user| user.login).collect();
+
+    let mut teams = BTreeMap::new();
+    let gh_teams: Vec<GhTeam> = github.get_all(github.url(&format!("/orgs/{}/teams", org))?).await?;
+    for team in gh_teams {
+        let team_path = format!("/orgs/{}/teams/{}", org, team.slug);
+        let members = fetch_roles(github, &format!("{}/members", team_path), &["maintainer", "member"]).await?;
+
+        let repos: Vec<GhTeamRepo> = github.get_all(github.url(&format!("{}/repos", team_path))?).await?;
+        let repos = repos.iter().map(|repo| (repo.full_name.clone(), repo.role())).collect();
+
+        teams.insert(team.slug, TeamSnapshot {
+            name: team.name,
+            parent: team.parent.map(|parent| parent.slug),
+            privacy: team.privacy,
+            members,
+            repos,
+        });
+    }
+
+    Ok(OrganisationSnapshot { members, outside_collaborators, teams })
+}
+
+/// Saves members with their roles, outside collaborators and teams with their members and repository permissions
+/// of the organisation `org` as `organisation.json` in the reserved directory of `backup_dir`.
+///
+/// Permissions which were granted, changed or revoked since the previous run are appended to
+/// `organisation_history.json`.
+pub async fn export_organisation(github: &GitHub, org: &str, backup_dir: &Path) -> Result<(), ExportError> {
+    let snapshot = fetch_snapshot(github, org).await?;
+
+    let dir = reserved_dir(backup_dir);
+    let path = dir.join("organisation.json");
+    if let Some(previous) = read_json::<OrganisationSnapshot>(&path) {
+        record_changes(&dir.join("organisation_history.json"), changed_fields(&previous.entries(), &snapshot.entries()))?;
+    }
+
+    write_json(&path, &snapshot)?;
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use git2::Repository;
+use crate::github::{GhRepo, TargetKind};
+use crate::mirror::repo_dir;
+use crate::state::BackupState;
+
+enum PlannedAction {
+    Clone,
+    Fetch,
+    Unchanged,
+    Skip,
+}
+
+impl Display for PlannedAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlannedAction::Clone => write!(f, "would clone"),
+            PlannedAction::Fetch => write!(f, "would fetch"),
+            PlannedAction::Unchanged => write!(f, "unchanged"),
+            PlannedAction::Skip => write!(f, "would skip"),
+        }
+    }
+}
+
+/// Decides what a backup run would do with the repository at `repo_dir` without modifying it.
+fn plan_repo(repo_dir: &Path) -> PlannedAction {
+    if !repo_dir.exists() {
+        return PlannedAction::Clone;
+    }
+
+    match Repository::open(repo_dir) {
+        Ok(_) => PlannedAction::Fetch,
+        Err(_) => PlannedAction::Skip,
+    }
+}
+
+/// Formats a size given in KiB, as reported by the GitHub API.
+fn format_size(kib: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = kib as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub fn print_plan(backup_dir: &Path, repos: &[GhRepo], kind: TargetKind, bare: bool, state: &BackupState) {
+    let mut clone = (0, 0);
+    let mut fetch = (0, 0);
+    let mut unchanged = 0;
+    let mut skip = 0;
+
+    println!("Backup plan for {}:", backup_dir.display());
+    for repo in repos {
+        let action = match plan_repo(&repo_dir(backup_dir, kind.repo_path(repo), bare)) {
+            PlannedAction::Fetch if state.is_unchanged(repo) => PlannedAction::Unchanged,
+            action => action,
+        };
+        match action {
+            PlannedAction::Clone => {
+                clone.0 += 1;
+                clone.1 += repo.size;
+            }
+            PlannedAction::Fetch => {
+                fetch.0 += 1;
+                fetch.1 += repo.size;
+            }
+            PlannedAction::Unchanged => unchanged += 1,
+            PlannedAction::Skip => skip += 1,
+        }
+        println!("  {:<12} {} ({})", action.to_string(), repo.full_name, format_size(repo.size));
+    }
+
+    println!("Would clone {} repos ({}), fetch {} repos ({}), leave {} unchanged and skip {} repos.",
+             clone.0, format_size(clone.1), fetch.0, format_size(fetch.1), unchanged, skip);
+    println!("Estimated total size: {}", format_size(clone.1 + fetch.1));
+}
@@ -0,0 +1,72 @@
+use std::path::Path;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::export::{read_json, write_json, ExportError};
+use crate::github::GitHub;
+
+/// Version of the layout below `pulls/`. Exports with a different version are fetched again from scratch.
+const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct PullsState {
+    version: u32,
+    /// Latest `updated_at` of all exported pull requests.
+    since: Option<String>,
+}
+
+/// Exports pull requests with their reviews and review comments of the repository `full_name` to `dir`.
+///
+/// Every pull request is stored as `<number>.json`. The pull request object lists the requested reviewers and
+/// teams, review comments carry the diff hunk they refer to. The commits of pull requests are part of the mirror
+/// as `refs/pull/<number>/head` and `refs/pull/<number>/merge`.
+pub async fn export_pulls(github: &GitHub, full_name: &str, dir: &Path) -> Result<(), ExportError> {
+    let state_path = dir.join("state.json");
+    let since = read_json::<PullsState>(&state_path)
+        .filter(|state| state.version == LAYOUT_VERSION)
+        .and_then(|state| state.since);
+
+    // Unlike issues, pull requests cannot be filtered by `since`. Sorting by update time allows to stop at the
+    // first pull request which did not change since the last export. Like `since` of issues, the comparison is
+    // inclusive, as a pull request may have been updated again within the same second after the last export.
+    let mut url = github.url(&format!("/repos/{}/pulls", full_name))?;
+    url.query_pairs_mut()
+        .append_pair("state", "all")
+        .append_pair("sort", "updated")
+        .append_pair("direction", "desc");
+
+    let pulls: Vec<Value> = github.get_while(url, |pull: &Value| {
+        match (pull["updated_at"].as_str(), &since) {
+            (Some(updated_at), Some(since)) => updated_at >= since.as_str(),
+            _ => true,
+        }
+    }).await?;
+
+    let latest = pulls.iter()
+        .filter_map(|pull| pull["updated_at"].as_str())
+        .max()
+        .map(str::to_string)
+        .or(since);
+
+    for pull in pulls {
+        let number = pull["number"].as_u64().unwrap_or_default();
+
+        let url = github.url(&format!("/repos/{}/pulls/{}/reviews", full_name, number))?;
+        let reviews: Vec<Value> = github.get_all(url).await?;
+
+        let url = github.url(&format!("/repos/{}/pulls/{}/comments", full_name, number))?;
+        let review_comments: Vec<Value> = github.get_all(url).await?;
+
+        write_json(&dir.join(format!("{}.json", number)), &json!({
+            "pull": pull,
+            "reviews": reviews,
+            "review_comments": review_comments,
+        }))?;
+    }
+
+    write_json(&state_path, &PullsState {
+        version: LAYOUT_VERSION,
+        since: latest,
+    })?;
+
+    Ok(())
+}
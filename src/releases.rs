@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::export::{download_verified, read_json, write_json, ExportError};
+use crate::github::GitHub;
+
+#[derive(Deserialize)]
+struct GhAsset {
+    id: u64,
+    name: String,
+    size: u64,
+    /// Checksum like `sha256:<hex>`. Only reported for assets uploaded since mid 2025.
+    digest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GhRelease {
+    id: u64,
+    #[serde(default)]
+    assets: Vec<GhAsset>,
+}
+
+/// Entry of `assets.json`, which records every downloaded asset by its id.
+#[derive(Serialize, Deserialize)]
+struct AssetRecord {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+/// Downloads the asset to `path` and returns its SHA-256 checksum. The size and, if reported, the checksum are
+/// verified before the asset is moved into place.
+async fn download_asset(github: &GitHub, full_name: &str, asset: &GhAsset, path: &Path) -> Result<String, ExportError> {
+    let url = github.url(&format!("/repos/{}/releases/assets/{}", full_name, asset.id))?;
+    let response = github.download(&url).await?;
+
+    let sha256 = asset.digest.as_deref().and_then(|digest| digest.strip_prefix("sha256:"));
+    download_verified(response, path, asset.size, sha256, &asset.name).await
+}
+
+/// Exports the releases of the repository `full_name` to `dir`.
+///
+/// The metadata of all releases is written to `releases.json`, assets to `<release id>/<asset name>`. Assets
+/// whose id and size did not change since the last export are not downloaded again.
+pub async fn export_releases(github: &GitHub, full_name: &str, dir: &Path) -> Result<(), ExportError> {
+    let url = github.url(&format!("/repos/{}/releases", full_name))?;
+    let releases: Vec<Value> = github.get_all(url).await?;
+    write_json(&dir.join("releases.json"), &releases)?;
+
+    let index_path = dir.join("assets.json");
+    let mut index: BTreeMap<u64, AssetRecord> = read_json(&index_path).unwrap_or_default();
+
+    for release in releases {
+        let Ok(release) = serde_json::from_value::<GhRelease>(release) else {
+            continue;
+        };
+
+        for asset in release.assets {
+            // Asset names are file names, but make sure they stay inside the release directory.
+            let relative = format!("{}/{}", release.id, asset.name.replace(['/', '\\'], "_"));
+            let path = dir.join(&relative);
+
+            let unchanged = index.get(&asset.id).is_some_and(|record| {
+                record.size == asset.size && fs::metadata(dir.join(&record.path)).is_ok_and(|m| m.len() == asset.size)
+            });
+            if unchanged {
+                continue;
+            }
+
+            fs::create_dir_all(dir.join(release.id.to_string()))?;
+            let sha256 = download_asset(github, full_name, &asset, &path).await?;
+
+            index.insert(asset.id, AssetRecord {
+                path: relative,
+                size: asset.size,
+                sha256,
+            });
+            // Record progress after every asset, so an interrupted export does not download it again.
+            write_json(&index_path, &index)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::export::{data_dir, file_timestamp, RESERVED_DIR};
+use crate::github::{GhRepo, TargetKind};
+use crate::mirror::repo_dir;
+use crate::state::BackupState;
+
+/// Directory in the reserved directory which holds the backups of repositories that vanished upstream.
+const ATTIC: &str = "attic";
+
+/// Returns all paths which belong to the backup of the repository at the relative `path`: the mirror, the wiki and
+/// the exported data.
+fn backup_paths(backup_dir: &Path, path: &str, bare: bool) -> [PathBuf; 3] {
+    [
+        repo_dir(backup_dir, path, bare),
+        repo_dir(backup_dir, &format!("{}.wiki", path), bare),
+        data_dir(backup_dir, path),
+    ]
+}
+
+/// Moves the backup of the repository at `from` to `to`. Nothing is moved if any path at `to` is taken already.
+fn move_backup(backup_dir: &Path, from: &str, to: &str, bare: bool) -> io::Result<()> {
+    let sources = backup_paths(backup_dir, from, bare);
+    let targets = backup_paths(backup_dir, to, bare);
+
+    if let Some(taken) = targets.iter().find(|target| target.exists()) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", taken.display())));
+    }
+
+    for (source, target) in sources.iter().zip(&targets) {
+        if source.exists() {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(source, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Follows the repositories of the previous runs by their id to the unfiltered `repos` listed now.
+///
+/// Backups of renamed repositories are moved to their new name, so they are not cloned again. Backups of
+/// repositories which vanished upstream are orphaned: they are moved to `.gh-backup/attic/<name>-<timestamp>` instead
+/// of being deleted. In a `dry` run, this is only reported.
+pub fn relocate_backups(backup_dir: &Path, kind: TargetKind, bare: bool, repos: &[GhRepo], state: &mut BackupState, dry: bool) {
+    let listed: HashMap<u64, &GhRepo> = repos.iter().map(|repo| (repo.id, repo)).collect();
+    let now = file_timestamp(SystemTime::now());
+
+    for (id, full_name) in state.repos() {
+        let from = kind.full_name_path(&full_name);
+
+        match listed.get(&id) {
+            Some(repo) if repo.full_name == full_name => {}
+            // E.g. the owner was renamed, which does not change the path of a repository of an organisation.
+            Some(repo) if kind.repo_path(repo) == from => state.renamed(id, &repo.full_name),
+            Some(repo) => {
+                let to = kind.repo_path(repo);
+                if dry {
+                    println!("Would move {} to {} after it was renamed", full_name, repo.full_name);
+                    continue;
+                }
+
+                match move_backup(backup_dir, from, to, bare) {
+                    Ok(()) => {
+                        println!("Moved {} to {} after it was renamed", full_name, repo.full_name);
+                        state.renamed(id, &repo.full_name);
+                    }
+                    Err(e) => eprintln!("Failed to move renamed {} to {}: {}", full_name, repo.full_name, e),
+                }
+            }
+            None => {
+                let to = format!("{}/{}/{}-{}", RESERVED_DIR, ATTIC, from, now);
+                if dry {
+                    println!("Would move orphaned {} to {}", full_name, to);
+                    continue;
+                }
+
+                match move_backup(backup_dir, from, &to, bare) {
+                    Ok(()) => {
+                        println!("Moved orphaned {} to {}", full_name, to);
+                        state.forget(id);
+                    }
+                    Err(e) => eprintln!("Failed to move orphaned {} to {}: {}", full_name, to, e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::{Display, Formatter};
+use std::process::ExitCode;
+use crate::backup::BackupError;
+use crate::mirror::Mirrored;
+
+/// Exit code if some, but not all, repositories or targets failed to back up.
+const PARTIAL_FAILURE: u8 = 2;
+
+pub enum Outcome {
+    Cloned,
+    Updated,
+    Unchanged,
+    Failed(BackupError),
+}
+
+impl From<Mirrored> for Outcome {
+    fn from(mirrored: Mirrored) -> Self {
+        match mirrored {
+            Mirrored::Cloned => Outcome::Cloned,
+            Mirrored::Updated => Outcome::Updated,
+            Mirrored::Unchanged => Outcome::Unchanged,
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Cloned => write!(f, "cloned"),
+            Outcome::Updated => write!(f, "updated"),
+            Outcome::Unchanged => write!(f, "unchanged"),
+            Outcome::Failed(e) => write!(f, "failed: {}", e.kind),
+        }
+    }
+}
+
+pub struct RepoReport {
+    pub full_name: String,
+    pub outcome: Outcome,
+}
+
+pub fn print_summary(reports: &[RepoReport], failed_targets: &[String]) {
+    let width = reports.iter().map(|report| report.full_name.len()).max().unwrap_or(0).max("REPOSITORY".len());
+
+    println!();
+    println!("{:<width$}  OUTCOME", "REPOSITORY");
+    for report in reports {
+        println!("{:<width$}  {}", report.full_name, report.outcome);
+    }
+
+    let count = |predicate: fn(&Outcome) -> bool| reports.iter().filter(|report| predicate(&report.outcome)).count();
+    println!("Cloned {}, updated {}, skipped {} unchanged and failed {} of {} repos.",
+             count(|o| matches!(o, Outcome::Cloned)),
+             count(|o| matches!(o, Outcome::Updated)),
+             count(|o| matches!(o, Outcome::Unchanged)),
+             count(|o| matches!(o, Outcome::Failed(_))),
+             reports.len());
+
+    for target in failed_targets {
+        println!("Failed to back up {}.", target);
+    }
+}
+
+/// Returns success if everything was backed up, failure if nothing was backed up and a distinct exit code
+/// for partial failures.
+pub fn exit_code(reports: &[RepoReport], failed_targets: &[String]) -> ExitCode {
+    let failed = reports.iter().filter(|report| matches!(report.outcome, Outcome::Failed(_))).count();
+
+    if failed == 0 && failed_targets.is_empty() {
+        ExitCode::SUCCESS
+    } else if failed == reports.len() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::from(PARTIAL_FAILURE)
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+use git2::{ErrorClass, ErrorCode};
+
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Returns the delay before the retry `attempt`, starting at 1.
+///
+/// The delay doubles with every attempt and is jittered, so that parallel jobs which failed at the same time do
+/// not retry at the same time.
+pub fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY.saturating_mul(1 << attempt.saturating_sub(1).min(16)).min(MAX_DELAY);
+    let millis = delay.as_millis() as u64;
+    Duration::from_millis(fastrand::u64(millis / 2..=millis))
+}
+
+/// Extracts the status code from libgit2 HTTP errors like "unexpected http status code: 503".
+pub fn http_status(e: &git2::Error) -> Option<u16> {
+    let (_, status) = e.message().rsplit_once("status code: ")?;
+    status.trim().parse().ok()
+}
+
+/// Returns whether a git operation which failed with `e` may succeed when retried, e.g. after a dropped
+/// connection. Errors like failed authentication, missing or DMCA-disabled repositories are permanent.
+pub fn is_transient(e: &git2::Error) -> bool {
+    if matches!(e.code(), ErrorCode::Auth | ErrorCode::Certificate | ErrorCode::NotFound) {
+        return false;
+    }
+
+    match e.class() {
+        ErrorClass::Net | ErrorClass::Ssl => true,
+        ErrorClass::Http => match http_status(e) {
+            Some(status) => status == 429 || status >= 500,
+            None => true,
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_derive::{Deserialize, Serialize};
+use crate::export::{read_json, reserved_dir, write_json};
+use crate::github::GhRepo;
+
+/// Version of the state file. State with a different version is discarded, which leads to a full run.
+const STATE_VERSION: u32 = 1;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct RepoState {
+    full_name: String,
+    pushed_at: Option<String>,
+    updated_at: Option<String>,
+}
+
+impl From<&GhRepo> for RepoState {
+    fn from(repo: &GhRepo) -> Self {
+        RepoState {
+            full_name: repo.full_name.clone(),
+            pushed_at: repo.pushed_at.clone(),
+            updated_at: repo.updated_at.clone(),
+        }
+    }
+}
+
+/// Timestamps of the repositories at their last successful backup, keyed by repository id.
+#[derive(Default, Serialize, Deserialize)]
+pub struct BackupState {
+    version: u32,
+    repos: BTreeMap<u64, RepoState>,
+}
+
+fn state_path(backup_dir: &Path) -> PathBuf {
+    reserved_dir(backup_dir).join("state.json")
+}
+
+impl BackupState {
+    /// Loads the state of the backup in `backup_dir`. Missing or outdated state is treated as empty.
+    pub fn load(backup_dir: &Path) -> Self {
+        read_json::<BackupState>(&state_path(backup_dir))
+            .filter(|state| state.version == STATE_VERSION)
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, backup_dir: &Path) -> io::Result<()> {
+        write_json(&state_path(backup_dir), &BackupState {
+            version: STATE_VERSION,
+            repos: self.repos.clone(),
+        })
+    }
+
+    /// Returns whether neither `pushed_at` nor `updated_at` of `repo` moved since its last successful backup.
+    /// Repositories whose listing lacks `pushed_at` are never considered unchanged.
+    pub fn is_unchanged(&self, repo: &GhRepo) -> bool {
+        repo.pushed_at.is_some() && self.repos.get(&repo.id) == Some(&RepoState::from(repo))
+    }
+
+    /// Records that `repo` was backed up successfully.
+    pub fn backed_up(&mut self, repo: &GhRepo) {
+        self.repos.insert(repo.id, RepoState::from(repo));
+    }
+
+    /// Records that the backup of `repo` failed, so it is backed up again by the next run. The repository is still
+    /// tracked, as its backup directory exists.
+    pub fn failed(&mut self, repo: &GhRepo) {
+        if let Some(state) = self.repos.get_mut(&repo.id) {
+            state.pushed_at = None;
+        }
+    }
+
+    /// Returns the ids and full names of all repositories which were backed up before.
+    pub fn repos(&self) -> Vec<(u64, String)> {
+        self.repos.iter().map(|(id, state)| (*id, state.full_name.clone())).collect()
+    }
+
+    /// Records that the backup of the repository `id` was moved to `full_name`.
+    pub fn renamed(&mut self, id: u64, full_name: &str) {
+        if let Some(state) = self.repos.get_mut(&id) {
+            state.full_name = full_name.to_string();
+        }
+    }
+
+    /// Forgets the repository `id`, whose backup was moved away.
+    pub fn forget(&mut self, id: u64) {
+        self.repos.remove(&id);
+    }
+}
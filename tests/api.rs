@@ -0,0 +1,1229 @@
+//! Runs gh-backup against a mock GitHub Enterprise Server API.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use git2::{Repository, Signature};
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn json(body: impl Into<String>) -> Self {
+        Response {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    fn status(status: u16) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: r#"{"message": "error"}"#.to_string(),
+        }
+    }
+
+    fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+/// A minimal HTTP server which answers every request with the response returned by its handler.
+struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    /// Requests which carried an `Authorization` header.
+    authorized_requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &str) -> Response + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let authorized_requests = Arc::new(Mutex::new(vec![]));
+
+        let server_url = url.clone();
+        let server_requests = requests.clone();
+        let server_authorized_requests = authorized_requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let target = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+
+                let mut content_length = 0;
+                let mut authorized = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                        if name.eq_ignore_ascii_case("authorization") {
+                            authorized = true;
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                std::io::Read::read_exact(&mut reader, &mut body).unwrap();
+
+                server_requests.lock().unwrap().push(target.clone());
+                if authorized {
+                    server_authorized_requests.lock().unwrap().push(target.clone());
+                }
+                let response = handler(&server_url, &target);
+
+                let mut head = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+                                       response.status, response.body.len());
+                for (name, value) in response.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str("\r\n");
+
+                stream.write_all(head.as_bytes()).unwrap();
+                stream.write_all(response.body.as_bytes()).unwrap();
+            }
+        });
+
+        MockServer { url, requests, authorized_requests }
+    }
+
+    fn api_url(&self) -> String {
+        format!("{}/api/v3", self.url)
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn authorized_requests(&self) -> Vec<String> {
+        self.authorized_requests.lock().unwrap().clone()
+    }
+}
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gh-backup-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn repo_json(id: u64, name: &str, clone_url: &str) -> String {
+    format!(r#"{{"id": {id}, "name": "{name}", "full_name": "acme/{name}", "clone_url": "{clone_url}", "size": 2048}}"#)
+}
+
+fn gh_backup(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gh-backup"))
+        .args(args)
+        .env("GH_TOKEN", "secret")
+        .output()
+        .unwrap()
+}
+
+fn create_source_repo(path: &Path) -> git2::Oid {
+    let repo = Repository::init(path).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let tree = repo.treebuilder(None).unwrap().write().unwrap();
+    let tree = repo.find_tree(tree).unwrap();
+    let commit = repo.commit(Some("refs/heads/main"), &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+    commit
+}
+
+#[test]
+fn dry_run_follows_pagination_of_enterprise_api() {
+    let server = MockServer::start(|url, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            Response::json(format!("[{}]", repo_json(1, "first", "https://ghe.example.com/acme/first.git")))
+                .header("Link", format!(r#"<{}/api/v3/orgs/acme/repos?type=all&per_page=100&page=2>; rel="next""#, url))
+        }
+        "/api/v3/orgs/acme/repos?type=all&per_page=100&page=2" => Response::json(format!(
+            "[{}, {}]",
+            repo_json(2, "second", "https://ghe.example.com/acme/second.git"),
+            repo_json(3, "third", "https://ghe.example.com/acme/third.git")
+        )),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("dry").join("backup");
+    Repository::init(backup_dir.join("first")).unwrap();
+    fs::create_dir_all(backup_dir.join("second")).unwrap();
+    fs::write(backup_dir.join("second/notes.txt"), "not a repository").unwrap();
+
+    let output = gh_backup(&["--dry", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let planned = |action: &str, name: &str| stdout.lines().any(|line| line.contains(action) && line.contains(name));
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(planned("would fetch", "acme/first (2.0 MiB)"), "{}", stdout);
+    assert!(planned("would skip", "acme/second (2.0 MiB)"), "{}", stdout);
+    assert!(planned("would clone", "acme/third (2.0 MiB)"), "{}", stdout);
+    assert!(stdout.contains("Would clone 1 repos (2.0 MiB), fetch 1 repos (2.0 MiB), leave 0 unchanged and skip 1 repos."), "{}", stdout);
+    assert!(stdout.contains("Estimated total size: 4.0 MiB"), "{}", stdout);
+    assert!(!backup_dir.join("third").exists());
+    assert!(!backup_dir.join(".gh-backup").exists());
+    assert!(server.requests().iter().all(|request| request.starts_with("/api/v3/")));
+}
+
+#[test]
+fn mirrors_clone_url_returned_by_api() {
+    let dir = test_dir("mirror");
+    let source = dir.join("source");
+    let commit = create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mirror = Repository::open_bare(backup_dir.join("tool.git")).unwrap();
+    assert_eq!(mirror.refname_to_id("refs/heads/main").unwrap(), commit);
+}
+
+#[test]
+fn reports_unknown_organisation() {
+    let server = MockServer::start(|_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("unknown").join("backup");
+
+    let output = gh_backup(&["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Organisation not found."));
+}
+
+#[test]
+fn exits_with_distinct_code_on_partial_failure() {
+    let dir = test_dir("partial");
+    let source = dir.join("source");
+    create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+    let missing_url = format!("file://{}", dir.join("missing").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            Response::json(format!("[{}, {}]", repo_json(1, "tool", &clone_url), repo_json(2, "gone", &missing_url)))
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout.contains("Cloned 1, updated 0, skipped 0 unchanged and failed 1 of 2 repos."), "{}", stdout);
+    assert!(!backup_dir.join("gone").exists());
+    assert!(!backup_dir.join("gone.partial").exists());
+
+    let output = gh_backup(&["--dry", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|line| line.contains("would fetch") && line.contains("acme/tool")), "{}", stdout);
+    assert!(stdout.lines().any(|line| line.contains("would clone") && line.contains("acme/gone")), "{}", stdout);
+    assert!(stdout.contains("Would clone 1 repos (2.0 MiB), fetch 1 repos (2.0 MiB)"), "{}", stdout);
+}
+
+#[test]
+fn reports_corrupted_mirror_and_backs_up_the_other_repos() {
+    let dir = test_dir("corrupted");
+    let source = dir.join("source");
+    create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            Response::json(format!("[{}, {}]", repo_json(1, "tool", &clone_url), repo_json(2, "site", &clone_url)))
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    fs::create_dir_all(backup_dir.join("tool")).unwrap();
+
+    let output = gh_backup(&["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to backup acme/tool: Failed to open repository"));
+    assert!(stdout.contains("Cloned 1, updated 0, skipped 0 unchanged and failed 1 of 2 repos."), "{}", stdout);
+    Repository::open(backup_dir.join("site")).unwrap();
+}
+
+#[test]
+fn waits_for_rate_limit_instead_of_failing() {
+    let limited = AtomicBool::new(true);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" if limited.swap(false, Ordering::SeqCst) => {
+            Response::status(403)
+                .header("X-RateLimit-Remaining", "0")
+                .header("Retry-After", "1")
+        }
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json("[]"),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("ratelimit").join("backup");
+
+    let output = gh_backup(&["--dry", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Waited 1 times for the GitHub API rate limit to reset."));
+    assert_eq!(server.requests().iter().filter(|request| *request == "/api/v3/user").count(), 2);
+}
+
+#[test]
+fn reports_forbidden_without_rate_limit_as_permission_error() {
+    let server = MockServer::start(|_, _| Response::status(403));
+    let backup_dir = test_dir("forbidden").join("backup");
+
+    let output = gh_backup(&["--dry", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Access forbidden."));
+}
+
+#[test]
+fn retries_server_errors() {
+    let failing = AtomicBool::new(true);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" if failing.swap(false, Ordering::SeqCst) => Response::status(502),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json("[]"),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("retry").join("backup");
+
+    let output = gh_backup(&["--dry", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn gives_up_on_server_errors_without_retries() {
+    let server = MockServer::start(|_, _| Response::status(502));
+    let backup_dir = test_dir("noretry").join("backup");
+
+    let output = gh_backup(&["--dry", "--retries", "0", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Server error."));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn mirrors_wiki_next_to_repo() {
+    let dir = test_dir("wiki");
+    create_source_repo(&dir.join("source/tool.git"));
+    let wiki_commit = create_source_repo(&dir.join("source/tool.wiki.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1, "has_wiki": true}}]"#,
+            clone_url
+        )),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--bare", "--wiki", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let wiki = Repository::open_bare(backup_dir.join("tool.wiki.git")).unwrap();
+    assert_eq!(wiki.refname_to_id("refs/heads/main").unwrap(), wiki_commit);
+}
+
+#[test]
+fn ignores_wiki_which_was_never_created() {
+    let dir = test_dir("no-wiki");
+    create_source_repo(&dir.join("source/tool.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1, "has_wiki": true}}]"#,
+            clone_url
+        )),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--bare", "--wiki", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Cloned 1, updated 0, skipped 0 unchanged and failed 0 of 1 repos."), "{}", stdout);
+    assert!(backup_dir.join("tool.git").exists());
+    assert!(!backup_dir.join("tool.wiki.git").exists());
+    assert!(!backup_dir.join("tool.wiki.git.partial").exists());
+}
+
+#[test]
+fn exports_issues_incrementally() {
+    let dir = test_dir("issues");
+    create_source_repo(&dir.join("source/tool.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1, "has_issues": true}}]"#,
+            clone_url
+        )),
+        "/api/v3/repos/acme/tool/issues?state=all&sort=updated&direction=asc&per_page=100" => {
+            Response::json(r#"[{"number": 7, "title": "Broken", "comments": 1, "updated_at": "2024-01-02T00:00:00Z"}]"#)
+        }
+        "/api/v3/repos/acme/tool/issues?state=all&sort=updated&direction=asc&since=2024-01-02T00%3A00%3A00Z&per_page=100" => {
+            Response::json("[]")
+        }
+        "/api/v3/repos/acme/tool/issues/7/comments?per_page=100" => Response::json(r#"[{"id": 1, "body": "Indeed"}]"#),
+        "/api/v3/repos/acme/tool/labels?per_page=100" => Response::json(r#"[{"name": "bug"}]"#),
+        "/api/v3/repos/acme/tool/milestones?state=all&per_page=100" => Response::json("[]"),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--issues", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let issue = fs::read_to_string(backup_dir.join("tool.github/issues/7.json")).unwrap();
+    assert!(issue.contains("Broken") && issue.contains("Indeed"));
+    assert!(fs::read_to_string(backup_dir.join("tool.github/issues/labels.json")).unwrap().contains("bug"));
+    assert_eq!(server.requests().iter().filter(|request| request.contains("/comments")).count(), 1);
+}
+
+#[test]
+fn exports_pull_requests_and_mirrors_pull_refs() {
+    let dir = test_dir("pulls");
+    let source = dir.join("source/tool.git");
+    let commit = create_source_repo(&source);
+    Repository::open(&source).unwrap().reference("refs/pull/3/head", commit, false, "pull request").unwrap();
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/api/v3/repos/acme/tool/pulls?state=all&sort=updated&direction=desc&per_page=100" => {
+            Response::json(r#"[{"number": 3, "title": "Feature", "updated_at": "2024-01-02T00:00:00Z", "requested_reviewers": []},
+                               {"number": 2, "title": "Fix", "updated_at": "2024-01-01T00:00:00Z", "requested_reviewers": []}]"#)
+        }
+        "/api/v3/repos/acme/tool/pulls/3/reviews?per_page=100" => Response::json(r#"[{"id": 1, "state": "APPROVED"}]"#),
+        "/api/v3/repos/acme/tool/pulls/3/comments?per_page=100" => {
+            Response::json(r#"[{"id": 2, "body": "Nit", "diff_hunk": "@@ -1 +1 @@"}]"#)
+        }
+        "/api/v3/repos/acme/tool/pulls/2/reviews?per_page=100" | "/api/v3/repos/acme/tool/pulls/2/comments?per_page=100" => {
+            Response::json("[]")
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--bare", "--pulls", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let pull = fs::read_to_string(backup_dir.join("tool.github/pulls/3.json")).unwrap();
+    assert!(pull.contains("APPROVED") && pull.contains("@@ -1 +1 @@"));
+    // The latest pull request may have been updated again within the same second, so it is exported again.
+    let reviews = |number| server.requests().iter().filter(|request| request.contains(&format!("/pulls/{}/reviews", number))).count();
+    assert_eq!(reviews(3), 2);
+    assert_eq!(reviews(2), 1);
+
+    let mirror = Repository::open_bare(backup_dir.join("tool.git")).unwrap();
+    assert_eq!(mirror.refname_to_id("refs/pull/3/head").unwrap(), commit);
+}
+
+#[test]
+fn downloads_release_assets_once() {
+    let dir = test_dir("releases");
+    create_source_repo(&dir.join("source/tool.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/api/v3/repos/acme/tool/releases?per_page=100" => Response::json(r#"[{"id": 10, "tag_name": "v1.0", "assets": [
+            {"id": 6, "name": "tool.tar.download", "size": 5},
+            {"id": 5, "name": "tool.tar.gz", "size": 5,
+             "digest": "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"}
+        ]}]"#),
+        "/api/v3/repos/acme/tool/releases/assets/5" => Response::json("hello"),
+        "/api/v3/repos/acme/tool/releases/assets/6" => Response::json("notes"),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--releases", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    assert_eq!(fs::read_to_string(backup_dir.join("tool.github/releases/10/tool.tar.gz")).unwrap(), "hello");
+    assert_eq!(fs::read_to_string(backup_dir.join("tool.github/releases/10/tool.tar.download")).unwrap(), "notes");
+    assert!(fs::read_to_string(backup_dir.join("tool.github/releases/releases.json")).unwrap().contains("v1.0"));
+    assert_eq!(server.requests().iter().filter(|request| request.contains("/assets/5")).count(), 1);
+}
+
+#[test]
+fn rejects_corrupted_release_assets() {
+    let dir = test_dir("corrupted");
+    create_source_repo(&dir.join("source/tool.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/api/v3/repos/acme/tool/releases?per_page=100" => Response::json(r#"[{"id": 10, "assets": [
+            {"id": 5, "name": "tool.tar.gz", "size": 5, "digest": "sha256:0000"}
+        ]}]"#),
+        "/api/v3/repos/acme/tool/releases/assets/5" => Response::json("hello"),
+        "/api/v3/repos/acme/tool/releases/assets/6" => Response::json("notes"),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--releases", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Download of tool.tar.gz is corrupted."));
+    assert!(!backup_dir.join("tool.github/releases/10/tool.tar.gz").exists());
+}
+
+#[test]
+fn downloads_lfs_objects_of_all_refs() {
+    let dir = test_dir("lfs");
+    let source = dir.join("source");
+    let clone_url = format!("file://{}", source.display());
+    let oid = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    let server = MockServer::start(move |url, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/lfs/objects/batch" => Response::json(format!(r#"{{"objects": [{{"oid": "{oid}", "size": 5,
+            "actions": {{"download": {{"href": "{url}/lfs/objects/{oid}", "header": {{"Authorization": "Bearer abc"}}}}}}}}]}}"#)),
+        "/lfs/objects/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824" => Response::json("hello"),
+        _ => Response::status(404),
+    });
+
+    // The default branch configures the LFS server, the pointer is only reachable from another branch. A pull
+    // request, which anyone can open, must not redirect the requests.
+    create_source_repo(&source);
+    let repo = Repository::open(&source).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let commit = |refname: &str, parent: git2::Oid, files: &[(&str, String)]| {
+        let parent = repo.find_commit(parent).unwrap();
+        let mut tree = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        for (name, content) in files {
+            tree.insert(name, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        repo.commit(Some(refname), &signature, &signature, "Change", &tree, &[&parent]).unwrap()
+    };
+    let main = commit("refs/heads/main", repo.refname_to_id("refs/heads/main").unwrap(), &[
+        (".gitattributes", "*.bin filter=lfs diff=lfs merge=lfs -text\n".to_string()),
+        (".lfsconfig", format!("[lfs]\n\turl = {}/lfs\n", server.url)),
+    ]);
+    commit("refs/heads/assets", main, &[
+        ("data.bin", format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize 5\n")),
+    ]);
+    commit("refs/pull/1/head", main, &[(".lfsconfig", format!("[lfs]\n\turl = {}/evil\n", server.url))]);
+
+    let backup_dir = dir.join("backup");
+    let args = ["--lfs", "--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let object = backup_dir.join(format!("tool.git/lfs/objects/2c/f2/{oid}"));
+    assert_eq!(fs::read_to_string(object).unwrap(), "hello");
+    assert_eq!(server.requests().iter().filter(|request| request.as_str() == "/lfs/objects/batch").count(), 1);
+    assert!(server.requests().iter().all(|request| !request.starts_with("/evil")));
+
+    // The LFS server is not on the host of the clone URL, so it must not receive the token.
+    assert!(!server.authorized_requests().iter().any(|request| request.starts_with("/lfs/objects/batch")));
+    assert!(server.authorized_requests().contains(&format!("/lfs/objects/{oid}")));
+}
+
+#[test]
+fn downloads_lfs_objects_next_to_missing_ones() {
+    let dir = test_dir("lfs-missing");
+    let source = dir.join("source");
+    let clone_url = format!("file://{}", source.display());
+    let missing = "a".repeat(64);
+    let oid = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    let batch_missing = missing.clone();
+    let server = MockServer::start(move |url, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/lfs/objects/batch" => Response::json(format!(r#"{{"objects": [
+            {{"oid": "{batch_missing}", "size": 5, "error": {{"code": 404, "message": "Object does not exist"}}}},
+            {{"oid": "{oid}", "size": 5, "actions": {{"download": {{"href": "{url}/lfs/objects/{oid}"}}}}}}]}}"#)),
+        "/lfs/objects/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824" => Response::json("hello"),
+        _ => Response::status(404),
+    });
+
+    create_source_repo(&source);
+    let repo = Repository::open(&source).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let parent = repo.find_commit(repo.refname_to_id("refs/heads/main").unwrap()).unwrap();
+    let mut tree = repo.treebuilder(None).unwrap();
+    for (name, content) in [
+        (".gitattributes", "*.bin filter=lfs diff=lfs merge=lfs -text\n".to_string()),
+        (".lfsconfig", format!("[lfs]\n\turl = {}/lfs\n", server.url)),
+        ("missing.bin", format!("version https://git-lfs.github.com/spec/v1\noid sha256:{missing}\nsize 5\n")),
+        ("data.bin", format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize 5\n")),
+    ] {
+        tree.insert(name, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+    }
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+    repo.commit(Some("refs/heads/main"), &signature, &signature, "Add assets", &tree, &[&parent]).unwrap();
+
+    let backup_dir = dir.join("backup");
+    let output = gh_backup(&["--lfs", "--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains(&format!("1 objects are not available: {missing}: Object does not exist")), "{}", stderr);
+    let object = backup_dir.join(format!("tool.git/lfs/objects/2c/f2/{oid}"));
+    assert_eq!(fs::read_to_string(object).unwrap(), "hello");
+}
+
+#[test]
+fn downloads_lfs_objects_of_unchanged_repos_after_lfs_was_turned_on() {
+    let dir = test_dir("lfs-incremental");
+    let source = dir.join("source");
+    let clone_url = format!("file://{}", source.display());
+    let oid = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    let server = MockServer::start(move |url, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1,
+                 "pushed_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}}]"#,
+            clone_url
+        )),
+        "/lfs/objects/batch" => Response::json(format!(
+            r#"{{"objects": [{{"oid": "{oid}", "size": 5, "actions": {{"download": {{"href": "{url}/lfs/objects/{oid}"}}}}}}]}}"#
+        )),
+        "/lfs/objects/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824" => Response::json("hello"),
+        _ => Response::status(404),
+    });
+
+    create_source_repo(&source);
+    let repo = Repository::open(&source).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let parent = repo.find_commit(repo.refname_to_id("refs/heads/main").unwrap()).unwrap();
+    let mut tree = repo.treebuilder(None).unwrap();
+    for (name, content) in [
+        (".gitattributes", "*.bin filter=lfs diff=lfs merge=lfs -text\n".to_string()),
+        (".lfsconfig", format!("[lfs]\n\turl = {}/lfs\n", server.url)),
+        ("data.bin", format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize 5\n")),
+    ] {
+        tree.insert(name, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+    }
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+    repo.commit(Some("refs/heads/main"), &signature, &signature, "Add assets", &tree, &[&parent]).unwrap();
+
+    let backup_dir = dir.join("backup");
+    let args = ["--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&[&["--lfs"], &args[..]].concat());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("skipped 1 unchanged"));
+    let object = backup_dir.join(format!("tool.git/lfs/objects/2c/f2/{oid}"));
+    assert_eq!(fs::read_to_string(object).unwrap(), "hello");
+}
+
+#[test]
+fn mirrors_gists_of_organisation_members() {
+    let dir = test_dir("gists");
+    let source = dir.join("source");
+    let commit = create_source_repo(&source);
+    let pull_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "gists", &pull_url))),
+        "/api/v3/orgs/acme/members?per_page=100" => Response::json(r#"[{"login": "alice"}]"#),
+        "/api/v3/users/alice/gists?per_page=100" => {
+            Response::json(format!(r#"[{{"id": "abc", "git_pull_url": "{pull_url}", "comments": 1}}]"#))
+        }
+        "/api/v3/gists/abc/comments?per_page=100" => Response::json(r#"[{"id": 7, "body": "Thanks!"}]"#),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--gists", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Cloned 2, updated 0, skipped 0 unchanged and failed 0 of 2 repos."), "{}", stdout);
+    let mirror = Repository::open(backup_dir.join(".gh-backup/gists/alice/abc")).unwrap();
+    assert_eq!(mirror.refname_to_id("refs/heads/main").unwrap(), commit);
+    assert!(fs::read_to_string(backup_dir.join(".gh-backup/gists/alice/abc.github/comments.json")).unwrap().contains("Thanks!"));
+
+    // A repository named like the directory of gists does not collide with them.
+    Repository::open(backup_dir.join("gists")).unwrap();
+    assert!(!backup_dir.join("gists/alice").exists());
+
+    let manifest = fs::read_to_string(backup_dir.join(".gh-backup/manifest.json")).unwrap();
+    assert!(manifest.contains(r#""owner": "alice""#), "{}", manifest);
+}
+
+#[test]
+fn leaves_out_repo_named_like_reserved_directory() {
+    let dir = test_dir("reserved");
+    let source = dir.join("source");
+    create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            Response::json(format!("[{}, {}]", repo_json(1, ".gh-backup", &clone_url), repo_json(2, "tool", &clone_url)))
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot back up acme/.gh-backup"));
+    assert!(Repository::open(backup_dir.join(".gh-backup")).is_err());
+    Repository::open(backup_dir.join("tool")).unwrap();
+
+    let output = gh_backup(&["--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Repository::open_bare(backup_dir.join(".gh-backup.git")).unwrap();
+}
+
+#[test]
+fn records_changes_of_repository_settings() {
+    let dir = test_dir("metadata");
+    create_source_repo(&dir.join("source/tool.git"));
+    let clone_url = format!("file://{}", dir.join("source/tool.git").display());
+
+    let renamed = AtomicBool::new(false);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/api/v3/repos/acme/tool" if !renamed.swap(true, Ordering::SeqCst) => {
+            Response::json(r#"{"description": "A tool", "allow_squash_merge": true, "stargazers_count": 1}"#)
+        }
+        "/api/v3/repos/acme/tool" => {
+            Response::json(r#"{"description": "A tool", "allow_squash_merge": false, "stargazers_count": 2}"#)
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--metadata", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!backup_dir.join("tool.github/metadata_history.json").exists());
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let metadata = fs::read_to_string(backup_dir.join("tool.github/metadata.json")).unwrap();
+    assert!(metadata.contains(r#""allow_squash_merge": false"#));
+    let history = fs::read_to_string(backup_dir.join("tool.github/metadata_history.json")).unwrap();
+    assert!(history.contains("allow_squash_merge"));
+    assert!(!history.contains("stargazers_count"));
+}
+
+#[test]
+fn exports_organisation_structure_and_its_changes() {
+    let promoted = AtomicBool::new(false);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json("[]"),
+        "/api/v3/orgs/acme/members?role=admin&per_page=100" => Response::json(r#"[{"login": "octocat"}]"#),
+        "/api/v3/orgs/acme/members?role=member&per_page=100" => Response::json(r#"[{"login": "alice"}]"#),
+        "/api/v3/orgs/acme/outside_collaborators?per_page=100" => Response::json(r#"[{"login": "bob"}]"#),
+        "/api/v3/orgs/acme/teams?per_page=100" => Response::json(r#"[
+            {"slug": "core", "name": "Core", "privacy": "closed", "parent": null},
+            {"slug": "web", "name": "Web", "privacy": "closed", "parent": {"slug": "core"}}
+        ]"#),
+        "/api/v3/orgs/acme/teams/core/members?role=maintainer&per_page=100" => Response::json(r#"[{"login": "octocat"}]"#),
+        "/api/v3/orgs/acme/teams/web/members?role=member&per_page=100" => Response::json(r#"[{"login": "alice"}]"#),
+        "/api/v3/orgs/acme/teams/web/repos?per_page=100" if !promoted.swap(true, Ordering::SeqCst) => {
+            Response::json(r#"[{"full_name": "acme/site", "permissions": {"admin": false, "push": true, "pull": true}}]"#)
+        }
+        "/api/v3/orgs/acme/teams/web/repos?per_page=100" => {
+            Response::json(r#"[{"full_name": "acme/site", "role_name": "admin"}]"#)
+        }
+        target if target.ends_with("per_page=100") => Response::json("[]"),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("organisation").join("backup");
+    let args = ["--organisation", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let snapshot = fs::read_to_string(backup_dir.join(".gh-backup/organisation.json")).unwrap();
+    assert!(snapshot.contains(r#""parent": "core""#));
+    assert!(snapshot.contains(r#""acme/site": "push""#));
+    assert!(snapshot.contains("bob"));
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let history = fs::read_to_string(backup_dir.join(".gh-backup/organisation_history.json")).unwrap();
+    assert!(history.contains("team/web/repo/acme/site"));
+    assert!(!history.contains("member/alice"));
+}
+
+#[test]
+fn skips_repos_unchanged_since_last_backup() {
+    let dir = test_dir("incremental");
+    let source = dir.join("source/tool.git");
+    let first = create_source_repo(&source);
+    let wiki_first = create_source_repo(&dir.join("source/tool.wiki.git"));
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1, "has_wiki": true,
+                 "pushed_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}}]"#,
+            clone_url
+        )),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--bare", "--wiki", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // A push which GitHub does not report yet is not fetched, unless a full run is requested. Pushes to the wiki
+    // never move the timestamps, so the wiki is always fetched.
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let push = |path: &Path, parent: git2::Oid| {
+        let repo = Repository::open(path).unwrap();
+        let parent = repo.find_commit(parent).unwrap();
+        let commit = repo.commit(Some("refs/heads/main"), &signature, &signature, "Second", &parent.tree().unwrap(), &[&parent]);
+        commit.unwrap()
+    };
+    let second = push(&source, first);
+    let wiki_second = push(&dir.join("source/tool.wiki.git"), wiki_first);
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("skipped 1 unchanged"));
+    let mirror = Repository::open_bare(backup_dir.join("tool.git")).unwrap();
+    assert_eq!(mirror.refname_to_id("refs/heads/main").unwrap(), first);
+    let wiki = Repository::open_bare(backup_dir.join("tool.wiki.git")).unwrap();
+    assert_eq!(wiki.refname_to_id("refs/heads/main").unwrap(), wiki_second);
+
+    let output = gh_backup(&[&["--full"], &args[..]].concat());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mirror.refname_to_id("refs/heads/main").unwrap(), second);
+}
+
+#[test]
+fn writes_manifest_of_each_run() {
+    let dir = test_dir("manifest");
+    let source = dir.join("source");
+    let commit = create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            Response::json(format!("[{}, {}]", repo_json(1, "tool", &clone_url), repo_json(2, "manifests", &clone_url)))
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+
+    let output = gh_backup(&["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // A repository named like the directory of manifests does not collide with it.
+    let repo = Repository::open(backup_dir.join("manifests")).unwrap();
+    assert!(repo.statuses(None).unwrap().is_empty());
+
+    let manifest = fs::read_to_string(backup_dir.join(".gh-backup/manifest.json")).unwrap();
+    assert!(manifest.contains(&format!(r#""refs/heads/main": "{}""#, commit)));
+    assert!(manifest.contains(r#""outcome": "cloned""#));
+    assert!(manifest.contains(r#""id": 1"#));
+
+    let history: Vec<_> = fs::read_dir(backup_dir.join(".gh-backup/manifests")).unwrap().collect();
+    assert_eq!(history.len(), 1);
+}
+
+#[test]
+fn filters_repos_by_patterns_and_ignore_file() {
+    let server = MockServer::start(|_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}, {}, {}, {}]",
+            repo_json(1, "tool", "https://ghe.example.com/acme/tool.git"),
+            repo_json(2, "tool-sandbox", "https://ghe.example.com/acme/tool-sandbox.git"),
+            repo_json(3, "vendor-openssl", "https://ghe.example.com/acme/vendor-openssl.git"),
+            repo_json(4, "site", "https://ghe.example.com/acme/site.git"),
+        )),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("filter").join("backup");
+    fs::create_dir_all(&backup_dir).unwrap();
+    fs::write(backup_dir.join(".gh-backup-ignore"), "# Too large\nvendor-*\n").unwrap();
+
+    let output = gh_backup(&["--dry", "--include", "acme/t*", "--include", "vendor-*", "--exclude", "*-sandbox",
+        "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("acme/tool "), "{}", stdout);
+    assert!(!stdout.contains("acme/tool-sandbox"));
+    assert!(!stdout.contains("acme/vendor-openssl"));
+    assert!(!stdout.contains("acme/site"));
+}
+
+#[test]
+fn filters_repos_by_properties_of_listing() {
+    let server = MockServer::start(|_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(r#"[
+            {"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "https://ghe.example.com/acme/tool.git",
+             "size": 2048, "visibility": "internal", "topics": ["backend"]},
+            {"id": 2, "name": "linux", "full_name": "acme/linux", "clone_url": "https://ghe.example.com/acme/linux.git",
+             "size": 4096, "fork": true, "private": true, "topics": ["backend"]},
+            {"id": 3, "name": "old", "full_name": "acme/old", "clone_url": "https://ghe.example.com/acme/old.git",
+             "size": 10, "archived": true, "private": true, "topics": ["backend"]},
+            {"id": 4, "name": "huge", "full_name": "acme/huge", "clone_url": "https://ghe.example.com/acme/huge.git",
+             "size": 99999, "private": true, "topics": ["backend"]},
+            {"id": 5, "name": "site", "full_name": "acme/site", "clone_url": "https://ghe.example.com/acme/site.git",
+             "size": 10, "private": false, "topics": ["backend"]},
+            {"id": 6, "name": "docs", "full_name": "acme/docs", "clone_url": "https://ghe.example.com/acme/docs.git",
+             "size": 10, "private": true, "topics": ["docs"]}
+        ]"#),
+        _ => Response::status(404),
+    });
+    let backup_dir = test_dir("properties").join("backup");
+
+    let output = gh_backup(&["--dry", "--no-forks", "--archived", "skip", "--visibility", "private,internal",
+        "--max-size", "10", "--topic", "backend", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("acme/tool"), "{}", stdout);
+    for left_out in ["acme/linux", "acme/old", "acme/huge", "acme/site", "acme/docs"] {
+        assert!(!stdout.contains(left_out), "{}", stdout);
+    }
+}
+
+#[test]
+fn moves_renamed_and_orphaned_repos() {
+    let dir = test_dir("relocate");
+    create_source_repo(&dir.join("source/tool"));
+    create_source_repo(&dir.join("source/attic"));
+    let source = dir.join("source");
+
+    let renamed = AtomicBool::new(false);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            let tool_url = format!("file://{}", source.join("tool").display());
+            let attic_url = format!("file://{}", source.join("attic").display());
+            if renamed.swap(true, Ordering::SeqCst) {
+                Response::json(format!(r#"[{{"id": 1, "name": "cli", "full_name": "acme/cli", "clone_url": "{}", "size": 1}}]"#, tool_url))
+            } else {
+                Response::json(format!(
+                    r#"[{{"id": 1, "name": "tool", "full_name": "acme/tool", "clone_url": "{}", "size": 1}},
+                        {{"id": 2, "name": "attic", "full_name": "acme/attic", "clone_url": "{}", "size": 1}}]"#,
+                    tool_url, attic_url
+                ))
+            }
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = gh_backup(&args);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Cloned 0, updated 1"), "{}", stdout);
+
+    assert!(!backup_dir.join("tool").exists());
+    Repository::open(backup_dir.join("cli")).unwrap();
+    // The orphaned repository is named like the attic, which must not be moved into itself.
+    assert!(!backup_dir.join("attic").exists());
+    let attic: Vec<_> = fs::read_dir(backup_dir.join(".gh-backup/attic")).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+    assert_eq!(attic.len(), 1);
+    assert!(attic[0].to_string_lossy().starts_with("attic-"));
+}
+
+#[test]
+fn prunes_deleted_refs_and_updates_moved_refs() {
+    let dir = test_dir("prune");
+    let source = dir.join("source");
+    let first = create_source_repo(&source);
+    let repo = Repository::open(&source).unwrap();
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let first_commit = repo.find_commit(first).unwrap();
+    repo.branch("feature", &first_commit, false).unwrap();
+    repo.tag_lightweight("v1", first_commit.as_object(), false).unwrap();
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--bare", "--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mirror = Repository::open_bare(backup_dir.join("tool.git")).unwrap();
+    assert_eq!(mirror.refname_to_id("refs/heads/feature").unwrap(), first);
+    assert_eq!(mirror.refname_to_id("refs/tags/v1").unwrap(), first);
+
+    repo.find_branch("feature", git2::BranchType::Local).unwrap().delete().unwrap();
+    repo.tag_delete("v1").unwrap();
+    let tree = first_commit.tree().unwrap();
+    let second = repo.commit(Some("refs/heads/main"), &signature, &signature, "Second", &tree, &[&first_commit]).unwrap();
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mirror.refname_to_id("refs/heads/main").unwrap(), second);
+    assert!(mirror.refname_to_id("refs/heads/feature").is_err());
+    assert!(mirror.refname_to_id("refs/tags/v1").is_err());
+}
+
+#[test]
+fn backs_up_all_targets_of_config_file() {
+    let dir = test_dir("config");
+    let source = dir.join("source");
+    create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => Response::json(format!("[{}]", repo_json(1, "tool", &clone_url))),
+        "/api/v3/users/bob/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 2, "name": "dotfiles", "full_name": "bob/dotfiles", "clone_url": "{}", "size": 1}}]"#,
+            clone_url
+        )),
+        _ => Response::status(404),
+    });
+    let config = dir.join("config.toml");
+    fs::write(&config, format!(r#"
+        api_url = "{}"
+        jobs = 2
+
+        [[target]]
+        name = "acme"
+        backup_dir = "{}"
+        bare = true
+
+        [[target]]
+        kind = "user"
+        name = "bob"
+        backup_dir = "{}"
+    "#, server.api_url(), dir.join("acme").display(), dir.join("bob").display())).unwrap();
+
+    let output = gh_backup(&["--config", config.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Cloned 2, updated 0, skipped 0 unchanged and failed 0 of 2 repos."), "{}", stdout);
+    Repository::open_bare(dir.join("acme/tool.git")).unwrap();
+    Repository::open(dir.join("bob/dotfiles")).unwrap();
+}
+
+#[test]
+fn rejects_unknown_keys_in_config_file() {
+    let dir = test_dir("config-typo");
+    let config = dir.join("config.toml");
+    fs::write(&config, "[[target]]\nname = \"acme\"\nwikis = true\n").unwrap();
+
+    let output = gh_backup(&["--config", config.to_str().unwrap()]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown key `wikis`"));
+}
+
+#[test]
+fn rejects_targets_sharing_a_backup_dir() {
+    let dir = test_dir("config-shared");
+    let server = MockServer::start(|_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        _ => Response::json("[]"),
+    });
+    let config = dir.join("config.toml");
+    fs::write(&config, format!(r#"
+        api_url = "{}"
+
+        [[target]]
+        name = "acme"
+        backup_dir = "{}"
+
+        [[target]]
+        name = "globex"
+        backup_dir = "{}"
+    "#, server.api_url(), dir.join("shared").display(), dir.join("./shared").display())).unwrap();
+
+    let output = gh_backup(&["--config", config.to_str().unwrap()]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Several targets share the backup directory"));
+    assert!(!dir.join("shared").exists());
+}
+
+#[test]
+fn rejects_options_of_a_target_with_config_file() {
+    let dir = test_dir("config-options");
+    let config = dir.join("config.toml");
+    fs::write(&config, "[[target]]\nname = \"acme\"\n").unwrap();
+
+    for option in [&["--wiki"][..], &["--include", "tool"], &["--kind", "user"], &["--archived", "skip"]] {
+        let output = gh_backup(&[&["--config", config.to_str().unwrap()], option].concat());
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be combined with --config"));
+    }
+}
+
+#[test]
+fn keeps_backups_in_place_after_owner_was_renamed() {
+    let dir = test_dir("owner-renamed");
+    create_source_repo(&dir.join("source/tool"));
+    let clone_url = format!("file://{}", dir.join("source/tool").display());
+
+    let renamed = AtomicBool::new(false);
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/orgs/acme/repos?type=all&per_page=100" => {
+            let owner = if renamed.swap(true, Ordering::SeqCst) { "acme-corp" } else { "acme" };
+            Response::json(format!(
+                r#"[{{"id": 1, "name": "tool", "full_name": "{}/tool", "clone_url": "{}", "size": 1,
+                     "pushed_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"}}]"#,
+                owner, clone_url
+            ))
+        }
+        _ => Response::status(404),
+    });
+    let backup_dir = dir.join("backup");
+    let args = ["--api-url", &server.api_url(), "--backup-dir", backup_dir.to_str().unwrap(), "acme"];
+
+    let output = gh_backup(&args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    for _ in 0..2 {
+        let output = gh_backup(&args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+        assert!(!stderr.contains("Failed to move"), "{}", stderr);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("skipped 1 unchanged"));
+    }
+
+    Repository::open(backup_dir.join("tool")).unwrap();
+    assert!(fs::read_to_string(backup_dir.join(".gh-backup/state.json")).unwrap().contains("acme-corp/tool"));
+}
+
+#[test]
+fn backs_up_repos_of_users_and_of_the_authenticated_user() {
+    let dir = test_dir("kinds");
+    let source = dir.join("source");
+    create_source_repo(&source);
+    let clone_url = format!("file://{}", source.display());
+
+    let server = MockServer::start(move |_, target| match target {
+        "/api/v3/user" => Response::json(r#"{"login": "octocat"}"#),
+        "/api/v3/users/bob/repos?type=all&per_page=100" => Response::json(format!(
+            r#"[{{"id": 1, "name": "dotfiles", "full_name": "bob/dotfiles", "clone_url": "{}", "size": 1}}]"#,
+            clone_url
+        )),
+        "/api/v3/user/repos?affiliation=owner%2Ccollaborator%2Corganization_member&visibility=all&per_page=100" => {
+            Response::json(format!(
+                r#"[{{"id": 2, "name": "tool", "full_name": "octocat/tool", "clone_url": "{0}", "size": 1}},
+                    {{"id": 3, "name": "tool", "full_name": "acme/tool", "clone_url": "{0}", "size": 1}}]"#,
+                clone_url
+            ))
+        }
+        _ => Response::status(404),
+    });
+
+    let user_dir = dir.join("bob");
+    let output = gh_backup(&["--kind", "user", "--api-url", &server.api_url(), "--backup-dir", user_dir.to_str().unwrap(), "bob"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Repository::open(user_dir.join("dotfiles")).unwrap();
+
+    // Repositories of the authenticated user may belong to several owners with the same repository names.
+    let me_dir = dir.join("me");
+    let output = gh_backup(&["--kind", "me", "--api-url", &server.api_url(), "--backup-dir", me_dir.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Repository::open(me_dir.join("octocat/tool")).unwrap();
+    Repository::open(me_dir.join("acme/tool")).unwrap();
+
+    assert!(server.requests().iter().all(|request| !request.starts_with("/api/v3/orgs/")));
+}
+
+#[test]
+fn requires_a_name_exactly_for_organisations_and_users() {
+    let output = gh_backup(&["--dry", "--kind", "me", "octocat"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The target name must be omitted for kind me."));
+
+    for kind in ["org", "user"] {
+        let output = gh_backup(&["--dry", "--kind", kind]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Name of the organisation or user is missing."));
+    }
+}